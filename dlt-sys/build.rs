@@ -1,21 +1,110 @@
+#[cfg(feature = "bindgen")]
 extern crate bindgen;
+extern crate cc;
 extern crate cmake;
 extern crate dotenv;
+extern crate pkg_config;
 
 use std::collections::HashMap;
 use std::env;
+use std::fmt::Write;
+use std::fs;
 use std::path::{ Path, PathBuf };
-use std::process::Command;
 use std::string::String;
 
+#[cfg(feature = "bindgen")]
 use bindgen::callbacks::{ ParseCallbacks, IntKind };
 
 type CMakeOptions = HashMap<String, String>;
 
+// Cargo features of `dlt-sys` and the CMake options, off by default, they turn on
+const FEATURE_OPTIONS: &[(&str, &str)] = &[
+    ("shm",             "DLT_SYS_WITH_DLT_SHM_ENABLE"),
+    ("dlt-test",        "DLT_SYS_WITH_DLTTEST"),
+    ("systemd",         "DLT_SYS_WITH_SYSTEMD"),
+    ("systemd-journal", "DLT_SYS_WITH_SYSTEMD_JOURNAL"),
+    ("dlt-system",      "DLT_SYS_WITH_DLT_SYSTEM")
+];
+
+// CMake options, the `cfg` flag they set and the C definition the headers test, if any
+const CFG_OPTIONS: &[(&str, &str, Option<&str>)] = &[
+    ("DLT_SYS_WITH_DLT_SHM_ENABLE",   "dlt_shm",             Some("DLT_SHM_ENABLE")),
+    ("DLT_SYS_WITH_DLTTEST",          "dlt_test",            Some("DLT_TEST_ENABLE")),
+    ("DLT_SYS_WITH_SYSTEMD",          "dlt_systemd",         None),
+    ("DLT_SYS_WITH_SYSTEMD_JOURNAL",  "dlt_systemd_journal", None),
+    ("DLT_SYS_WITH_DLT_USE_IPv6",     "dlt_ipv6",            Some("DLT_USE_IPv6")),
+    ("DLT_SYS_WITH_DLT_SYSTEM",       "dlt_system",          None),
+    ("DLT_SYS_WITH_DLT_FILETRANSFER", "dlt_filetransfer",    None)
+];
+
+// Structures checked by the layout tests: name, the CMake option they depend on and fields
+const LAYOUT_TYPES: &[(&str, Option<&str>, &[&str])] = &[
+    ("DltContext",                     None, &["contextID", "log_level_pos", "log_level_ptr", "trace_status_ptr", "mcnt"]),
+    ("DltContextData",                 None, &["handle", "buffer", "size", "log_level", "trace_status", "args_num",
+                                               "context_description"]),
+    ("DltUserInjectionCallback",       None, &["service_id", "injection_callback"]),
+    ("DltUserLogLevelChangedCallback", None, &["contextID", "log_level", "trace_status", "log_level_changed_callback"]),
+    ("dlt_ll_ts_type",                 None, &["contextID", "log_level", "log_level_ptr", "trace_status", "trace_status_ptr",
+                                               "context_description", "injection_table", "nrcallbacks",
+                                               "log_level_changed_callback"]),
+    ("dlt_env_ll_item",                None, &["appId", "ctxId", "ll"]),
+    ("dlt_env_ll_set",                 None, &["item", "array_size", "num_elem"]),
+    ("DltUser",                        None, &["ecuID", "appID", "dlt_log_handle", "dlt_user_handle",
+                                               "dlt_segmented_queue_read_handle", "dlt_segmented_queue_write_handle",
+                                               "dlt_segmented_nwt_handle", "dlt_is_file", "dlt_ll_ts",
+                                               "dlt_ll_ts_max_num_entries", "dlt_ll_ts_num_entries", "overflow",
+                                               "overflow_counter", "application_description", "receiver", "verbose_mode",
+                                               "use_extende_header_for_non_verbose", "with_session_id", "with_timestamp",
+                                               "with_ecu_id", "enable_local_print", "local_print_mode", "log_state",
+                                               "startup_buffer", "resend_buffer", "timeout_at_exit_handler",
+                                               "initial_ll_set"]),
+    ("DltUser", Some("DLT_SYS_WITH_DLT_SHM_ENABLE"), &["dlt_shm"]),
+    ("DltUser", Some("DLT_SYS_WITH_DLTTEST"),        &["corrupt_user_header", "corrupt_message_size",
+                                                       "corrupt_message_size_size"]),
+    ("DltStorageHeader",               None, &["pattern", "seconds", "microseconds", "ecu"]),
+    ("DltStandardHeader",              None, &["htyp", "mcnt", "len"]),
+    ("DltStandardHeaderExtra",         None, &["ecu", "seid", "tmsp"]),
+    ("DltExtendedHeader",              None, &["msin", "noar", "apid", "ctid"]),
+    ("DltMessage",                     None, &["found_serialheader", "resync_offset", "headersize", "datasize",
+                                               "headerbuffer", "databuffer", "databuffersize", "storageheader",
+                                               "standardheader", "headerextra", "extendedheader"]),
+    ("DltFilter",                      None, &["apid", "ctid", "counter"]),
+    ("DltFile",                        None, &["handle", "index", "counter", "counter_total", "position", "file_length",
+                                               "file_position", "error_messages", "filter", "filter_counter", "msg"]),
+    ("DltReceiver",                    None, &["lastBytesRcvd", "bytesRcvd", "totalBytesRcvd", "buffer", "buf", "fd",
+                                               "buffersize"]),
+    ("DltBuffer",                      None, &["shm", "size", "mem", "min_size", "max_size", "step_size"]),
+    ("DltShm",                         None, &["shmid", "semid", "buffer"]),
+    ("DltShmBlockHead",                None, &["head", "status", "size"])
+];
+
+// Constants the sizes of the arrays above come from
+const LAYOUT_CONSTANTS: &[&str] = &[
+    "DLT_ID_SIZE",
+    "DLT_FILTER_MAX",
+    "DLT_USER_BUF_MAX_SIZE",
+    "DLT_USER_RESENDBUF_MAX_SIZE"
+];
+
+// Version of libdlt the bindings in `src/` were written for
+const BINDINGS_VERSION: (&str, &str) = ("2", "17");
+
+// Targets the hand-written bindings in `src/` are used for, others(musl ones included) need the
+// `bindgen` feature. They aren't generated per target, the layout tests(run for each of these in
+// CI) check them.
+#[cfg(not(feature = "bindgen"))]
+const BINDINGS_TARGETS: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "aarch64-unknown-linux-gnu",
+    "armv7-unknown-linux-gnueabihf"
+];
+
 // Used in bindgen's "parse_callbacks"
+#[cfg(feature = "bindgen")]
 #[derive(Debug)]
 struct DltMacroTypes;
 
+#[cfg(feature = "bindgen")]
 impl ParseCallbacks for DltMacroTypes {
     fn int_macro(&self, name: &str, _value: i64) -> Option<IntKind> {
         // Sorted
@@ -38,24 +127,15 @@ impl ParseCallbacks for DltMacroTypes {
 }
 
 fn main() {
-    // Making sure that the `dlt-daemon` submodule is available when trying to compile it
-    if !Path::new("dlt-daemon/.git").exists() {
-        let _ = Command::new("git").args(&["submodule", "update", "--init"])
-                                   .status()
-                                   .expect("Failed to initialize the \"dlt-daemon\" submodule. \
-                                            Make sure you have \"Git\" installed or that you don't \
-                                            have issues with the internet(cloning from GitHub)");
-    }
-
     let target = env::var("TARGET").expect("Cargo build scripts always have TARGET");
     let host   = env::var("HOST").expect("Cargo build scripts always have HOST");
 
-    // Register `dlt_sys` default values for the DLT CMake flags
-    let mut cmake_options = register_cmake_defaults();
-
     // Loading from `.env` user-preferred `dlt_sys` configurations
     dotenv::dotenv().ok();
 
+    // Register `dlt_sys` default values for the DLT CMake flags
+    let mut cmake_options = register_cmake_defaults();
+
     // Overwrite default configurations with user-selected configurations
     for (variable_name, value) in std::env::vars() {
         // All of our variables start with `DLT_SYS_`, skip the others
@@ -75,38 +155,258 @@ fn main() {
         }
     }
 
+    // Cargo features turn options on, whatever the environment says
+    for &(feature, key) in FEATURE_OPTIONS {
+        if env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase().replace("-", "_"))).is_some() {
+            cmake_options.insert(key.to_owned(), "ON".to_owned());
+        }
+    }
+
+    // With a system library, the options describe how it was built
     let cmake_options = cmake_options;
     configure_dlt_features(&cmake_options);
 
-    let mut dst = cmake::Config::new("dlt-daemon");
-    for (key, value) in &cmake_options {
+    // Prefer the libdlt of the system(Yocto images, distributions) when asked to
+    if use_system_library() {
+        if let Some(include_dir) = probe_system_library() {
+            let include_dirs = [include_dir];
+            generate_version(&include_dirs);
+            generate_bindings(&target, &host, &include_dirs, &cmake_options);
+            generate_layout_tests(&include_dirs, &cmake_options);
+            return;
+        }
+
+        println!("cargo:warning=libdlt(automotive-dlt) wasn't found through pkg-config, \
+                  building the bundled dlt-daemon instead");
+    }
+
+    build_bundled(&target, &host, &cmake_options);
+}
+
+fn build_bundled(target: &str, host: &str, cmake_options: &CMakeOptions) {
+    // Never fetched from the network: the sources are part of the published crate, or of the
+    // checkout once the submodule is initialized
+    let source_dir = dlt_daemon_sources();
+
+    let mut dst = cmake::Config::new(&source_dir);
+    for (key, value) in cmake_options {
         // Removing the "DLT_SYS_" prefix before passing the configurations to CMake
         let (_, key) = key.split_at(8);
-        dst.define(key, &value);
+        dst.define(key, value);
     }
 
-    let dst = dst.build();
+    if target != host {
+        configure_cross_compilation(&mut dst, target);
+    }
+
+    // Without the daemon only libdlt is built, the install step(which needs everything) is skipped
+    let daemon = env::var_os("CARGO_FEATURE_DAEMON").is_some();
+    if !daemon {
+        dst.build_target("dlt");
+    }
 
-    if is_cmake_option_activated(&cmake_options, "DLT_SYS_BUILD_SHARED_LIBS") {
-        println!("cargo:rustc-link-search=native={}/build/dlt-build/lib64", dst.display());
-        println!("cargo:rustc-link-search=native={}/build/dlt-build/lib", dst.display());
-        println!("cargo:rustc-link-search=native={}/src/lib", dst.display());
+    let dst   = dst.build();
+    let build = dst.join("build");
+
+    let shared = is_cmake_option_activated(cmake_options, "DLT_SYS_BUILD_SHARED_LIBS");
+    let (include_dirs, lib_dirs) = if daemon {
+        let installed = build.join("dlt-build");
+        let lib_dirs  = if shared {
+            vec![installed.join("lib64"), installed.join("lib")]
+        } else {
+            vec![installed.join("lib64/static"), installed.join("lib/static")]
+        };
+
+        (vec![installed.join("include/dlt")], lib_dirs)
+    } else {
+        // Generated headers(`dlt_version.h`, ...) are next to the objects
+        (vec![source_dir.join("include/dlt"), build.join("include/dlt")], vec![build.join("src/lib")])
+    };
+
+    for lib_dir in &lib_dirs {
+        println!("cargo:rustc-link-search=native={}", lib_dir.display());
+    }
+    if shared {
         println!("cargo:rustc-link-lib=dlt");
     } else {
-        println!("cargo:rustc-link-search=native={}/build/dlt-build/lib64/static", dst.display());
-        println!("cargo:rustc-link-search=native={}/build/dlt-build/lib/static", dst.display());
-        println!("cargo:rustc-link-search=native={}/src/lib", dst.display());
         println!("cargo:rustc-link-lib=static=dlt");
     }
 
-    // Generating bindings after CMake build, so we can use the final headers installed by CMake
-    generate_bindings(&target, &host);
-
-    std::fs::copy(format!("{}/build/dlt-build/bin/dlt-daemon", dst.display()),
-                  format!("{}/../../../dlt-daemon", dst.display())).unwrap();
+    // Generating bindings after CMake build, so we can use the final headers of the build
+    generate_version(&include_dirs);
+    generate_bindings(target, host, &include_dirs, cmake_options);
+    generate_layout_tests(&include_dirs, cmake_options);
+
+    // For the build scripts of the crates depending on `dlt-sys`: `DEP_DLT_INCLUDE`,
+    // `DEP_DLT_GENERATED_INCLUDE`(`dlt_version.h`, the same directory unless libdlt is built
+    // alone), `DEP_DLT_LIB_DIR`, `DEP_DLT_BIN_DIR` and `DEP_DLT_DAEMON_PATH`
+    println!("cargo:include={}", include_dirs[0].display());
+    println!("cargo:generated_include={}", include_dirs[include_dirs.len() - 1].display());
+    if let Some(lib_dir) = lib_dirs.iter().find(|lib_dir| lib_dir.exists()) {
+        println!("cargo:lib_dir={}", lib_dir.display());
+    }
+    if daemon {
+        let bin_dir = build.join("dlt-build/bin");
+        println!("cargo:bin_dir={}", bin_dir.display());
+        println!("cargo:daemon_path={}", bin_dir.join("dlt-daemon").display());
+    }
 }
 
 //          =============== Helpers ===============
+// The `system` feature or `DLT_SYS_USE_SYSTEM=1`
+fn use_system_library() -> bool {
+    println!("cargo:rerun-if-env-changed=DLT_SYS_USE_SYSTEM");
+
+    if env::var_os("CARGO_FEATURE_SYSTEM").is_some() {
+        return true;
+    }
+
+    match env::var("DLT_SYS_USE_SYSTEM") {
+        Ok(value) => is_cmake_value_on(value.to_uppercase().as_str()),
+        Err(_)    => false
+    }
+}
+
+// The `DLT_SYS_DAEMON_SOURCE_DIR` tree, or the bundled one
+fn dlt_daemon_sources() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=DLT_SYS_DAEMON_SOURCE_DIR");
+
+    if let Some(source_dir) = env::var_os("DLT_SYS_DAEMON_SOURCE_DIR") {
+        let source_dir = PathBuf::from(source_dir);
+        if !source_dir.join("CMakeLists.txt").exists() {
+            panic!("DLT_SYS_DAEMON_SOURCE_DIR is set to \"{}\", which doesn't contain the dlt-daemon \
+                    sources(no CMakeLists.txt)", source_dir.display());
+        }
+
+        return source_dir;
+    }
+
+    let source_dir = PathBuf::from("dlt-daemon");
+    if !source_dir.join("CMakeLists.txt").exists() {
+        panic!("The dlt-daemon sources are missing from \"dlt-sys/dlt-daemon\". Either:\n\
+                - run `git submodule update --init` in your checkout of dlt-rs\n\
+                - point DLT_SYS_DAEMON_SOURCE_DIR at a local copy of https://github.com/GENIVI/dlt-daemon\n\
+                - use the libdlt of the system with DLT_SYS_USE_SYSTEM=1 or the `system` feature");
+    }
+
+    source_dir
+}
+
+// Toolchain, compiler and sysroot of the target for the CMake build
+fn configure_cross_compilation(dst: &mut cmake::Config, target: &str) {
+    match target_env("CMAKE_TOOLCHAIN_FILE", target) {
+        Some(toolchain_file) => {
+            dst.define("CMAKE_TOOLCHAIN_FILE", toolchain_file);
+        },
+        None => {
+            // What a toolchain file would set, CMake then stops running the host's compiler checks
+            if target.contains("-linux-") {
+                dst.define("CMAKE_SYSTEM_NAME", "Linux");
+            }
+            dst.define("CMAKE_SYSTEM_PROCESSOR", target.split('-').next().unwrap());
+        }
+    }
+
+    if let Some(compiler) = target_env("CC", target) {
+        dst.define("CMAKE_C_COMPILER", compiler);
+    }
+    if let Some(compiler) = target_env("CXX", target) {
+        dst.define("CMAKE_CXX_COMPILER", compiler);
+    }
+    for flag in target_flags("CFLAGS", target) {
+        dst.cflag(flag);
+    }
+    for flag in target_flags("CXXFLAGS", target) {
+        dst.cxxflag(flag);
+    }
+
+    if let Some(sysroot) = target_sysroot(target) {
+        // Libraries(zlib, systemd, ...) are looked for in the sysroot only, programs on the host
+        dst.define("CMAKE_SYSROOT", &sysroot)
+           .define("CMAKE_FIND_ROOT_PATH", &sysroot)
+           .define("CMAKE_FIND_ROOT_PATH_MODE_PROGRAM", "NEVER")
+           .define("CMAKE_FIND_ROOT_PATH_MODE_LIBRARY", "ONLY")
+           .define("CMAKE_FIND_ROOT_PATH_MODE_INCLUDE", "ONLY")
+           .define("CMAKE_FIND_ROOT_PATH_MODE_PACKAGE", "ONLY");
+    }
+}
+
+// A variable for the target, looked up like the `cc` crate does: `CC_aarch64-unknown-linux-gnu`,
+// `CC_aarch64_unknown_linux_gnu`, `TARGET_CC` and then `CC`
+fn target_env(name: &str, target: &str) -> Option<String> {
+    let names = [
+        format!("{}_{}", name, target),
+        format!("{}_{}", name, target.replace("-", "_")),
+        format!("TARGET_{}", name),
+        name.to_owned()
+    ];
+
+    for name in &names {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
+
+    names.iter()
+         .filter_map(|name| env::var(name).ok())
+         .find(|value| !value.is_empty())
+}
+
+// `CFLAGS` or `CXXFLAGS` of the target, split
+fn target_flags(name: &str, target: &str) -> Vec<String> {
+    target_env(name, target).map(|flags| flags.split_whitespace().map(String::from).collect())
+                            .unwrap_or_default()
+}
+
+// `CMAKE_SYSROOT` for the target, or the one the target's compiler was configured with
+fn target_sysroot(target: &str) -> Option<PathBuf> {
+    if let Some(sysroot) = target_env("CMAKE_SYSROOT", target) {
+        return Some(PathBuf::from(sysroot));
+    }
+
+    // GCC cross compilers(Yocto SDKs, Debian's `gcc-aarch64-linux-gnu`) know their sysroot
+    let compiler = target_env("CC", target)?;
+    let output   = std::process::Command::new(compiler.split_whitespace().next()?)
+                                         .arg("-print-sysroot")
+                                         .output()
+                                         .ok()?;
+
+    let sysroot = String::from_utf8(output.stdout).ok()?;
+    let sysroot = sysroot.trim();
+    if output.status.success() && !sysroot.is_empty() && Path::new(sysroot).is_dir() {
+        Some(PathBuf::from(sysroot))
+    } else {
+        None
+    }
+}
+
+// Links the installed libdlt and returns the directory of its headers
+fn probe_system_library() -> Option<PathBuf> {
+    // The version the hand-written bindings and the `dlt` crate follow
+    let library = pkg_config::Config::new().atleast_version("2.17")
+                                           .probe("automotive-dlt")
+                                           .ok()?;
+
+    // The `.pc` file points either at `<prefix>/include/dlt` or at `<prefix>/include`
+    let include_dir = library.include_paths.iter()
+                                           .flat_map(|path| vec![path.clone(), path.join("dlt")])
+                                           .find(|path| path.join("dlt_user.h").exists())?;
+
+    // Same metadata as for the bundled build, the daemon is the installed one if there is one
+    println!("cargo:include={}", include_dir.display());
+    println!("cargo:generated_include={}", include_dir.display());
+    if let Some(lib_dir) = library.link_paths.first() {
+        println!("cargo:lib_dir={}", lib_dir.display());
+    }
+    if let Ok(prefix) = pkg_config::get_variable("automotive-dlt", "prefix") {
+        let bin_dir = Path::new(&prefix).join("bin");
+        if bin_dir.join("dlt-daemon").exists() {
+            println!("cargo:bin_dir={}", bin_dir.display());
+            println!("cargo:daemon_path={}", bin_dir.join("dlt-daemon").display());
+        }
+    }
+
+    Some(include_dir)
+}
+
 fn register_cmake_defaults() -> CMakeOptions {
     use std::str::FromStr;
 
@@ -135,6 +435,7 @@ fn register_cmake_defaults() -> CMakeOptions {
             ("DLT_SYS_WITH_TESTSCRIPTS",         "OFF"),
             ("DLT_SYS_WITH_GPROF",               "OFF"),
             ("DLT_SYS_WITH_DLT_USE_IPv6",        "ON"),
+            ("DLT_SYS_WITH_DLT_FILETRANSFER",    "ON"),
             ("DLT_SYS_DLT_USER",                 "Rustacean"),
             ("DLT_SYS_BUILD_SHARED_LIBS",        "OFF"),
             ("DLT_SYS_CMAKE_INSTALL_PREFIX",     "dlt-build"),
@@ -189,49 +490,190 @@ fn is_cmake_option_activated(cmake_options: &CMakeOptions, key: &str) -> bool {
     false
 }
 
+// Options changing the API or the layout of the structures become `cfg` flags of the crate
 fn configure_dlt_features(cmake_options: &CMakeOptions) {
-    fn add_cfg(cfg: &str) {
-        println!("cargo:rustc-cfg={}", cfg);
+    for &(key, cfg, _) in CFG_OPTIONS {
+        println!("cargo:rustc-check-cfg=cfg({})", cfg);
+        if is_cmake_option_activated(cmake_options, key) {
+            println!("cargo:rustc-cfg={}", cfg);
+        }
     }
+}
 
-    for (key, value) in cmake_options {
-        if is_cmake_value_on(value.as_str()) {
-            // Follows the order of the checks in the CMakeLists.txt file
-            match key.as_str() {
-                "DLT_SYS_WITH_DLT_SYSTEM" => {}, // find_package(ZLIB REQUIRED)
-                "DLT_SYS_WITH_DLT_DBUS" => {}, // pkg_check_modules(DBUS REQUIRED dbus-1)
-                "DLT_SYS_WITH_DLTTEST" => {}, // add_definitions
-                "DLT_SYS_WITH_DLT_UNIT_TESTS" => {}, // add_definitions
-                "DLT_SYS_WITH_DLT_SHM_ENABLE" => {}, // add_definitions
-                "DLT_SYS_WITH_DLT_USE_IPv6" => {}, // add_definitions
-                "DLT_SYS_WITH_GPROF" => {}, // CMAKE_C_FLAGS = "-pg"
-                "DLT_SYS_WITH_DOC" => {},
-                "DLT_SYS_WITH_DLT_CXX11_EXT" => {},
-                "DLT_SYS_CMAKE_INSTALL_PREFIX" => {},
-                "DLT_SYS_WITH_SYSTEMD" => {},
-                "DLT_SYS_WITH_SYSTEMD_WATCHDOG" => {},
-                "DLT_SYS_WITH_SYSTEMD_JOURNAL" => {},
-                "DLT_SYS_WITH_DLT_UNIT_TESTS" => {},
-                _ => {}
+// `$OUT_DIR/version.rs`: the version of the headers, from `dlt_version.h`
+fn generate_version(include_dirs: &[PathBuf]) {
+    let header = include_dirs.iter()
+                             .map(|include_dir| include_dir.join("dlt_version.h"))
+                             .find(|header| header.exists())
+                             .expect("dlt_version.h is missing from the libdlt headers");
+    let header = fs::read_to_string(&header).unwrap();
+
+    // `#define _DLT_PACKAGE_MAJOR_VERSION "2"`
+    let definition = |name: &str| -> String {
+        header.lines()
+              .map(|line| line.split_whitespace().collect::<Vec<_>>())
+              .find(|words| words.len() > 2 && words[0] == "#define" && words[1] == name)
+              .map(|words| words[2].trim_matches('"').to_owned())
+              .unwrap_or_else(|| panic!("{} is missing from dlt_version.h", name))
+    };
+
+    let version = definition("_DLT_PACKAGE_VERSION");
+    let major   = definition("_DLT_PACKAGE_MAJOR_VERSION");
+    let minor   = definition("_DLT_PACKAGE_MINOR_VERSION");
+    let patch   = definition("_DLT_PACKAGE_PATCH_LEVEL");
+
+    // Structures change between minor versions, using the bindings with other headers would
+    // corrupt memory
+    if !cfg!(feature = "bindgen") && (major.as_str(), minor.as_str()) != BINDINGS_VERSION {
+        panic!("The bindings of dlt-sys are for libdlt {}.{}, the headers are for {}. Link libdlt {0}.{1} \
+                or enable the `bindgen` feature to generate bindings for {}",
+               BINDINGS_VERSION.0, BINDINGS_VERSION.1, version, version);
+    }
+
+    let source = format!("/// Version of the libdlt headers the crate was built with, from `dlt_version.h`\n\
+                          pub const DLT_PACKAGE_VERSION: &str = {:?};\n\
+                          pub const DLT_PACKAGE_MAJOR_VERSION: &str = {:?};\n\
+                          pub const DLT_PACKAGE_MINOR_VERSION: &str = {:?};\n\
+                          pub const DLT_PACKAGE_PATCH_LEVEL: &str = {:?};\n",
+                         version, major, minor, patch);
+    fs::write(PathBuf::from(env::var("OUT_DIR").unwrap()).join("version.rs"), source).unwrap();
+}
+
+// ctest-like checks of the Rust definitions against the headers: `$OUT_DIR/layout.c` reports
+// the sizes, alignments and offsets seen by the C compiler and `$OUT_DIR/layout_tests.rs` holds
+// one test per structure comparing them with the Rust ones
+fn generate_layout_tests(include_dirs: &[PathBuf], cmake_options: &CMakeOptions) {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    let mut c_source = String::from("/* Generated by the build script of dlt-sys */\n\
+                                     #include <stddef.h>\n\
+                                     #include <dlt_common.h>\n\
+                                     #include <dlt_user.h>\n\
+                                     #include <dlt_shm.h>\n\n");
+    let mut tests = String::from("// Generated by the build script of dlt-sys\n\n\
+                                  use super::*;\n\n\
+                                  fn size_of_pointee<T>(_: *const T) -> usize {\n    \
+                                      ::std::mem::size_of::<T>()\n\
+                                  }\n");
+
+    let mut probe = |name: &str, expression: &str| {
+        writeln!(c_source, "size_t dlt_sys_layout_{}(void) {{ return {}; }}", name, expression).unwrap();
+        writeln!(tests, "extern \"C\" {{ fn dlt_sys_layout_{}() -> usize; }}", name).unwrap();
+    };
+
+    for constant in LAYOUT_CONSTANTS {
+        probe(constant, constant);
+    }
+
+    let mut types: Vec<(&str, Vec<&str>)> = Vec::new();
+    for &(name, option, fields) in LAYOUT_TYPES {
+        if option.is_some_and(|option| !is_cmake_option_activated(cmake_options, option)) {
+            continue;
+        }
+
+        if types.iter().all(|&(known, _)| known != name) {
+            probe(&format!("{}_size", name), &format!("sizeof({})", name));
+            probe(&format!("{}_align", name), &format!("_Alignof({})", name));
+            types.push((name, Vec::new()));
+        }
+        for field in fields {
+            probe(&format!("{}_{}_offset", name, field), &format!("offsetof({}, {})", name, field));
+            probe(&format!("{}_{}_size", name, field), &format!("sizeof((({} *)0)->{})", name, field));
+        }
+        types.iter_mut().find(|&&mut (known, _)| known == name).unwrap().1.extend_from_slice(fields);
+    }
+
+    writeln!(tests, "\n#[test]\nfn constants() {{\n    unsafe {{").unwrap();
+    for constant in LAYOUT_CONSTANTS {
+        writeln!(tests, "        assert_eq!({0}, dlt_sys_layout_{0}(), \"{0}\");", constant).unwrap();
+    }
+    writeln!(tests, "    }}\n}}").unwrap();
+
+    for (name, fields) in types {
+        writeln!(tests, "\n#[test]\nfn layout_of_{}() {{\n    unsafe {{", name).unwrap();
+        writeln!(tests, "        assert_eq!(::std::mem::size_of::<{0}>(), dlt_sys_layout_{0}_size(), \"size of {0}\");", name).unwrap();
+        writeln!(tests, "        assert_eq!(::std::mem::align_of::<{0}>(), dlt_sys_layout_{0}_align(), \"alignment of {0}\");", name).unwrap();
+        writeln!(tests, "        let value = ::std::mem::MaybeUninit::<{}>::uninit();", name).unwrap();
+        for field in fields {
+            writeln!(tests, "        assert_eq!(::std::mem::offset_of!({0}, {1}), dlt_sys_layout_{0}_{1}_offset(), \"offset of {0}::{1}\");", name, field).unwrap();
+            writeln!(tests, "        assert_eq!(size_of_pointee(::std::ptr::addr_of!((*value.as_ptr()).{1})), dlt_sys_layout_{0}_{1}_size(), \"size of {0}::{1}\");", name, field).unwrap();
+        }
+        writeln!(tests, "    }}\n}}").unwrap();
+    }
+
+    let c_path = out_dir.join("layout.c");
+    fs::write(&c_path, c_source).unwrap();
+
+    // Compiled with the definitions the library was built with, only the tests link it
+    let mut build = cc::Build::new();
+    build.file(&c_path).includes(include_dirs).warnings(false);
+    for &(key, _, definition) in CFG_OPTIONS {
+        if let Some(definition) = definition {
+            if is_cmake_option_activated(cmake_options, key) {
+                build.define(definition, None);
             }
         }
     }
+
+    // A field missing from the headers(another libdlt version) fails the tests, not the build
+    if let Err(error) = build.try_compile("dlt_sys_layout") {
+        println!("cargo:warning=the layout tests can't run, {} doesn't compile: {}", c_path.display(), error);
+        tests = format!("#[test]\nfn layout_probe_compiles() {{\n    \
+                             panic!({:?});\n\
+                         }}\n", format!("{} doesn't compile against the headers: {}", c_path.display(), error));
+    }
+
+    fs::write(out_dir.join("layout_tests.rs"), tests).unwrap();
+}
+
+// The bindings in `src/` are used, as long as they fit the target
+#[cfg(not(feature = "bindgen"))]
+fn generate_bindings(target: &str, _host: &str, _include_dirs: &[PathBuf], _cmake_options: &CMakeOptions) {
+    if !BINDINGS_TARGETS.contains(&target) {
+        panic!("the bindings of dlt-sys aren't checked for {}, enable its `bindgen` feature to generate \
+                them(needs libclang)", target);
+    }
 }
 
-fn generate_bindings(target: &str, host: &str)
+// Regenerates the bindings from the installed headers, in `$OUT_DIR/bindings.rs`
+#[cfg(feature = "bindgen")]
+fn generate_bindings(target: &str, host: &str, include_dirs: &[PathBuf], cmake_options: &CMakeOptions)
 {
     let target_os    = target.splitn(3, "-").nth(2).unwrap();
     let mut bindings = bindgen::Builder::default();
 
     if target != host {
-        bindings = bindings.clang_args(&["--target", target.clone()]);
+        bindings = bindings.clang_args(&["--target", target]);
+
+        // Without the sysroot, clang would parse the headers of the host
+        if let Some(sysroot) = target_sysroot(target) {
+            bindings = bindings.clang_arg(format!("--sysroot={}", sysroot.display()))
+                               .clang_arg(format!("-isystem{}", sysroot.join("usr/include").display()));
+        }
+
+        // Only what changes the parsing of the headers, not the code generation flags
+        for flag in target_flags("CFLAGS", target) {
+            if flag.starts_with("-I") || flag.starts_with("-D") || flag.starts_with("-isystem") {
+                bindings = bindings.clang_arg(flag);
+            }
+        }
     }
 
-    let out_dir     = env::var("OUT_DIR").unwrap();
-    let out_dir     = PathBuf::from(&out_dir);
-    let include_dir = out_dir.join("build/dlt-build/include/dlt");
+    // The structures must have the layout the library was compiled with
+    for &(key, _, definition) in CFG_OPTIONS {
+        if let Some(definition) = definition {
+            if is_cmake_option_activated(cmake_options, key) {
+                bindings = bindings.clang_arg(format!("-D{}", definition));
+            }
+        }
+    }
 
-    bindings = bindings.clang_arg(format!("-I{}", include_dir.display()));
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = PathBuf::from(&out_dir);
+
+    for include_dir in include_dirs {
+        bindings = bindings.clang_arg(format!("-I{}", include_dir.display()));
+    }
 
     if target_os == "windows-msvc" {
         // There are so many possible paths, it is hard to make this work on
@@ -245,23 +687,32 @@ fn generate_bindings(target: &str, host: &str)
                 implementation(I don't have access to a Windows machine).");
     }
 
-    let header_path = |header| -> String {
-        include_dir.join(&header).into_os_string().into_string().unwrap()
-    };
+    // Installations don't always ship the headers of the optional parts(client, offline trace)
+    let headers = [
+        "dlt_version.h",
+        "dlt_types.h",
+        "dlt_protocol.h",
+        "dlt_user_macros.h",
+        "dlt_common.h",
+        "dlt_shm.h",
+        "dlt_user.h",
+        "dlt.h",
+        "dlt_filetransfer.h",
+        "dlt_common_api.h",
+        "dlt_client.h",
+        "dlt_offline_trace.h"
+    ];
+    let filetransfer = is_cmake_option_activated(cmake_options, "DLT_SYS_WITH_DLT_FILETRANSFER");
+    for header in headers.iter()
+                         .filter(|&&header| filetransfer || header != "dlt_filetransfer.h")
+                         .filter_map(|header| include_dirs.iter()
+                                                          .map(|include_dir| include_dir.join(header))
+                                                          .find(|header| header.exists()))
+    {
+        bindings = bindings.header(header.into_os_string().into_string().unwrap());
+    }
 
     let bindings = bindings
-        .header(header_path("dlt_version.h"))
-        .header(header_path("dlt_types.h"))
-        .header(header_path("dlt_protocol.h"))
-        .header(header_path("dlt_user_macros.h"))
-        .header(header_path("dlt_common.h"))
-        .header(header_path("dlt_shm.h"))
-        .header(header_path("dlt_user.h"))
-        .header(header_path("dlt.h"))
-        .header(header_path("dlt_filetransfer.h"))
-        .header(header_path("dlt_common_api.h"))
-        .header(header_path("dlt_client.h"))
-        .header(header_path("dlt_offline_trace.h"))
         .generate_comments(true)
         .trust_clang_mangling(false)
         .rustified_enum(".*")
@@ -109,7 +109,17 @@ pub struct DltMessage {
         + SIZE_OF_DLT_STANDARD_HEADER_EXTRA + SIZE_OF_DLT_EXTENDED_HEADER],
     /// Buffer for loading payload
     pub databuffer: *mut u8,
-    pub databuffersize: i32
+    pub databuffersize: i32,
+
+    // Header values of current loaded message
+    /// Pointer to storage header of current loaded header
+    pub storageheader: *mut DltStorageHeader,
+    /// Pointer to standard header of current loaded header
+    pub standardheader: *mut DltStandardHeader,
+    /// Extra parameters of current loaded header
+    pub headerextra: DltStandardHeaderExtra,
+    /// Pointer to extended header of current loaded header
+    pub extendedheader: *mut DltExtendedHeader
 }
 
 /// Structure to store filter parameters.
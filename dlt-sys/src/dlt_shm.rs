@@ -16,7 +16,7 @@ pub const DLT_SHM_SIZE: c_uint = 100000;
 /// Must be the same for server and client
 pub const DLT_SHM_SEM: c_uint = 22771;
 
-pub const DLT_SHM_HEAD: &'static [u8; 4] = b"SHM\x00";
+pub const DLT_SHM_HEAD: &[u8; 4] = b"SHM\x00";
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -2,6 +2,8 @@ use libc::{ c_int, c_uint, size_t, c_char, c_uchar, c_void, pthread_t, mqd_t };
 
 use dlt_types::{ DltReturnValue, DltLogLevelType, DltFormatType, DltNetworkTraceType, DltUserLogMode, DltTraceStatusType };
 use dlt_common::{ DltBuffer, DltReceiver, DLT_ID_SIZE };
+#[cfg(dlt_shm)]
+use dlt_shm::DltShm;
 
 /// Maximum size of each user buffer, also used for injection buffer
 pub const DLT_USER_BUF_MAX_SIZE: usize = 1390;
@@ -47,7 +49,7 @@ pub struct DltContextData {
 #[derive(Debug, Clone, Copy)]
 pub struct DltUserInjectionCallback {
     pub service_id: u32,
-    pub injection_callback: Option<unsafe extern fn(service_id: u32, data: *mut c_void, length: u32) -> c_int>
+    pub injection_callback: Option<unsafe extern "C" fn(service_id: u32, data: *mut c_void, length: u32) -> c_int>
 }
 
 #[repr(C)]
@@ -59,7 +61,7 @@ pub struct DltUserLogLevelChangedCallback {
     pub log_level: i8,
     /// Trace status
     pub trace_status: i8,
-    pub log_level_changed_callback: Option<unsafe extern fn(context_id: *mut c_char, log_level: u8, trace_status: u8)>
+    pub log_level_changed_callback: Option<unsafe extern "C" fn(context_id: *mut c_char, log_level: u8, trace_status: u8)>
 }
 
 /// This structure is used in a table managing all contexts and the corresponding log levels in an application
@@ -83,7 +85,7 @@ pub struct dlt_ll_ts_type {
     pub nrcallbacks: u32,
 
     /// Log level changed callback
-    pub log_level_changed_callback: Option<unsafe extern fn(context_id: *mut c_char, log_level: u8, trace_status: u8)>
+    pub log_level_changed_callback: Option<unsafe extern "C" fn(context_id: *mut c_char, log_level: u8, trace_status: u8)>
 }
 
 /// This structure holds initial log-level for given appId:ctxId pair
@@ -177,15 +179,15 @@ pub struct DltUser {
     pub timeout_at_exit_handler: u32,
     pub initial_ll_set: dlt_env_ll_set,
 
-    // #ifdef DLT_SHM_ENABLE
-    // pub dlt_shm: DltShm,
-    // #endif
+    #[cfg(dlt_shm)]
+    pub dlt_shm: DltShm,
 
-    // #ifdef DLT_TEST_ENABLE
-    // pub corrupt_user_header: c_int,
-    // pub corrupt_message_size: c_int,
-    // pub corrupt_message_size_size: i16,
-    // #endif
+    #[cfg(dlt_test)]
+    pub corrupt_user_header: c_int,
+    #[cfg(dlt_test)]
+    pub corrupt_message_size: c_int,
+    #[cfg(dlt_test)]
+    pub corrupt_message_size_size: i16,
 }
 
 extern "C" {
@@ -240,8 +242,8 @@ extern "C" {
     pub fn dlt_set_resend_timeout_atexit(timeout_in_milliseconds: u32) -> c_int;
     pub fn dlt_set_log_mode(mode: DltUserLogMode) -> DltReturnValue;
     pub fn dlt_get_log_state() -> c_int;
-    pub fn dlt_register_injection_callback(handle: *mut DltContext, service_id: u32, dlt_injection_callback: Option<unsafe extern fn (service_id: u32, data: *mut c_void, length: u32) -> c_int>) -> DltReturnValue;
-    pub fn dlt_register_log_level_changed_callback(handle: *mut DltContext, dlt_log_level_changed_callback: Option<unsafe extern fn (context_id: *mut c_char, log_level: u8, trace_status: u8)>) -> DltReturnValue;
+    pub fn dlt_register_injection_callback(handle: *mut DltContext, service_id: u32, dlt_injection_callback: Option<unsafe extern "C" fn(service_id: u32, data: *mut c_void, length: u32) -> c_int>) -> DltReturnValue;
+    pub fn dlt_register_log_level_changed_callback(handle: *mut DltContext, dlt_log_level_changed_callback: Option<unsafe extern "C" fn(context_id: *mut c_char, log_level: u8, trace_status: u8)>) -> DltReturnValue;
     pub fn dlt_verbose_mode() -> DltReturnValue;
     pub fn dlt_user_check_library_version(user_major_version: *const c_char, user_minor_version: *const c_char) -> DltReturnValue;
     pub fn dlt_nonverbose_mode() -> DltReturnValue;
@@ -267,12 +269,16 @@ extern "C" {
     pub fn dlt_user_atexit_blow_out_user_buffer() -> c_int;
     pub fn dlt_user_log_resend_buffer() -> DltReturnValue;
 
-// #ifdef DLT_TEST_ENABLE
-//void dlt_user_test_corrupt_user_header(int enable);
-//void dlt_user_test_corrupt_message_size(int enable,int16_t size);
-// #endif
+    #[cfg(dlt_test)]
+    pub fn dlt_user_test_corrupt_user_header(enable: c_int) -> DltReturnValue;
+    #[cfg(dlt_test)]
+    pub fn dlt_user_test_corrupt_message_size(enable: c_int, size: i16) -> DltReturnValue;
 }
 
+/// `DLT_IS_LOG_LEVEL_ENABLED`, a macro in `dlt_user.h`
+///
+/// # Safety
+/// `handle` must be null or point to a registered context.
 #[inline]
 pub unsafe fn dlt_user_is_logLevel_enabled(handle: *mut DltContext,
                                            loglevel: DltLogLevelType) -> DltReturnValue {
@@ -2,8 +2,37 @@
 
 extern crate libc;
 
+// Bindings of libdlt 2.17, written once against the headers. The layouts follow the target
+// through the `libc` types, the build script only accepts the targets CI runs the layout tests on.
+#[cfg(not(feature = "bindgen"))]
+mod dlt_types;
+#[cfg(not(feature = "bindgen"))]
+mod dlt_common;
+#[cfg(not(feature = "bindgen"))]
+mod dlt_user;
+#[cfg(not(feature = "bindgen"))]
+mod dlt_shm;
+
+#[cfg(not(feature = "bindgen"))]
+pub use dlt_types::*;
+#[cfg(not(feature = "bindgen"))]
+pub use dlt_common::*;
+#[cfg(not(feature = "bindgen"))]
+pub use dlt_user::*;
+#[cfg(not(feature = "bindgen"))]
+pub use dlt_shm::*;
+
+// Generated by the build script from the headers of the libdlt being linked
+#[cfg(feature = "bindgen")]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+include!(concat!(env!("OUT_DIR"), "/version.rs"));
+
+/// `DLT_IS_LOG_LEVEL_ENABLED`, a macro in `dlt_user.h` bindgen leaves out
+///
+/// # Safety
+/// `handle` must be null or point to a registered context.
+#[cfg(feature = "bindgen")]
 #[inline]
 pub unsafe fn dlt_user_is_logLevel_enabled(handle: *mut DltContext,
                                            loglevel: DltLogLevelType) -> DltReturnValue {
@@ -25,13 +54,20 @@ pub unsafe fn dlt_user_is_logLevel_enabled(handle: *mut DltContext,
     DltReturnValue::DLT_RETURN_LOGGING_DISABLED
 }
 
+// Sizes, alignments and offsets compared with the ones of the C compiler, generated by the
+// build script
+#[cfg(test)]
+mod layout {
+    include!(concat!(env!("OUT_DIR"), "/layout_tests.rs"));
+}
+
 #[test]
 fn hello_from_rust() {
     use std::ffi::CString;
     use std::ptr;
 
-    let dlt_major_version = CString::new("2").unwrap();
-    let dlt_minor_version = CString::new("17").unwrap();
+    let dlt_major_version = CString::new(DLT_PACKAGE_MAJOR_VERSION).unwrap();
+    let dlt_minor_version = CString::new(DLT_PACKAGE_MINOR_VERSION).unwrap();
     unsafe {
         // DLT_REGISTER_APP
         dlt_check_library_version(dlt_major_version.as_ptr(),
@@ -0,0 +1,223 @@
+//! Startup buffer of the user library: statistics, resending and what to do when it is full.
+//!
+//! Messages are kept in the user library's ring buffer while the daemon FIFO can't take them
+//! (daemon not started yet, FIFO full). When that buffer is full too, messages are lost.
+
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use libc::c_int;
+
+use ffi::{ self, DltReturnValue };
+use { check, duration_ms, Result };
+
+const POLICY_DROP_NEWEST: usize = 0;
+const POLICY_DROP_OLDEST: usize = 1;
+const POLICY_BLOCK: usize       = 2;
+
+static POLICY: AtomicUsize           = AtomicUsize::new(POLICY_DROP_NEWEST);
+static BLOCK_TIMEOUT_MS: AtomicUsize = AtomicUsize::new(0);
+/// Messages lost since the start of the process
+static LOST: AtomicUsize             = AtomicUsize::new(0);
+/// Timeout of the exit handler of the user library, in milliseconds, 1 second by default
+static RESEND_TIMEOUT_MS: AtomicUsize = AtomicUsize::new(1000);
+
+/// How long a blocked message waits between two attempts to empty the buffer
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+/// How long `flush_buffer` waits between two resends, the daemon empties the FIFO meanwhile
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Snapshot of the startup buffer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Size of the buffer, in bytes
+    pub total: usize,
+    /// Bytes waiting to be sent to the daemon
+    pub used: usize,
+    /// Messages waiting to be sent to the daemon
+    pub messages: usize,
+    /// Messages of this crate lost because the buffer was full, since the start of the process.
+    ///
+    /// With libdlt, messages other code of the process logs through libdlt itself aren't
+    /// counted: its `overflow_counter` isn't exported and is reset once reported to the daemon.
+    pub overflow_count: u32
+}
+
+/// What happens to a new message when both the FIFO and the startup buffer are full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The new message is lost, what libdlt does
+    DropNewest,
+    /// The oldest buffered messages make room for the new one.
+    ///
+    /// Only available with the `native` feature, libdlt doesn't expose its buffer.
+    DropOldest,
+    /// The logging thread keeps resending the buffer, for at most the given time,
+    /// before the new message is lost
+    Block(Duration)
+}
+
+/// Selects the overflow policy for all the messages of the process
+pub fn set_overflow_policy(policy: OverflowPolicy) -> Result<()> {
+    match policy {
+        OverflowPolicy::DropNewest => {
+            set_drop_oldest(false)?;
+            POLICY.store(POLICY_DROP_NEWEST, Ordering::Relaxed);
+        },
+        OverflowPolicy::DropOldest => {
+            set_drop_oldest(true)?;
+            POLICY.store(POLICY_DROP_OLDEST, Ordering::Relaxed);
+        },
+        OverflowPolicy::Block(timeout) => {
+            set_drop_oldest(false)?;
+            BLOCK_TIMEOUT_MS.store(duration_ms(timeout), Ordering::Relaxed);
+            POLICY.store(POLICY_BLOCK, Ordering::Relaxed);
+        }
+    }
+
+    Ok(())
+}
+
+/// The overflow policy in use
+pub fn overflow_policy() -> OverflowPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        POLICY_DROP_OLDEST => OverflowPolicy::DropOldest,
+        POLICY_BLOCK       => {
+            OverflowPolicy::Block(Duration::from_millis(BLOCK_TIMEOUT_MS.load(Ordering::Relaxed) as u64))
+        },
+        _ => OverflowPolicy::DropNewest
+    }
+}
+
+#[cfg(feature = "native")]
+fn set_drop_oldest(enable: bool) -> Result<()> {
+    ffi::dlt_user_set_drop_oldest(enable);
+    Ok(())
+}
+
+#[cfg(not(feature = "native"))]
+fn set_drop_oldest(enable: bool) -> Result<()> {
+    if enable {
+        Err(::Error::Unsupported("dropping the oldest messages needs the \"native\" feature"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "native")]
+fn dropped_oldest() -> usize {
+    ffi::dlt_user_get_dropped_oldest() as usize
+}
+
+#[cfg(not(feature = "native"))]
+fn dropped_oldest() -> usize {
+    0
+}
+
+#[cfg(feature = "native")]
+fn buffered_messages() -> usize {
+    ffi::dlt_user_get_buffered_messages()
+}
+
+// libdlt only tells the count through its exit handler, which returns it right away when it
+// may not wait
+#[cfg(not(feature = "native"))]
+fn buffered_messages() -> usize {
+    unsafe {
+        ffi::dlt_set_resend_timeout_atexit(0);
+        let count = ffi::dlt_user_atexit_blow_out_user_buffer();
+        ffi::dlt_set_resend_timeout_atexit(RESEND_TIMEOUT_MS.load(Ordering::Relaxed) as u32);
+        count.max(0) as usize
+    }
+}
+
+/// How long the exit handler of the user library resends the buffered messages
+pub(crate) fn set_resend_timeout(timeout: Duration) {
+    let timeout = duration_ms(timeout);
+    RESEND_TIMEOUT_MS.store(timeout, Ordering::Relaxed);
+    unsafe { ffi::dlt_set_resend_timeout_atexit(timeout as u32); }
+}
+
+/// Current usage of the startup buffer
+pub fn buffer_stats() -> Result<BufferStats> {
+    let mut total: c_int = 0;
+    let mut used: c_int  = 0;
+    check(unsafe { ffi::dlt_user_check_buffer(&mut total, &mut used) })?;
+
+    Ok(BufferStats {
+        total: total as usize,
+        used: used as usize,
+        // An empty buffer was never touched by the user library, it may not be initialised
+        messages: if used > 0 { buffered_messages() } else { 0 },
+        overflow_count: (LOST.load(Ordering::Relaxed) + dropped_oldest()) as u32
+    })
+}
+
+/// Tries once to send the buffered messages to the daemon
+pub fn resend_buffer() -> Result<()> {
+    check(unsafe { ffi::dlt_user_log_resend_buffer() })
+}
+
+/// Resends the buffered messages until the buffer is empty or `timeout` expires.
+///
+/// ### Returns
+/// The buffer statistics after the last attempt, `used` is `0` if everything was sent
+pub fn flush_buffer(timeout: Duration) -> Result<BufferStats> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        // A failed attempt only means the daemon isn't reachable yet
+        let _ = resend_buffer();
+
+        let stats = buffer_stats()?;
+        if stats.used == 0 || Instant::now() >= deadline {
+            return Ok(stats);
+        }
+
+        thread::sleep(FLUSH_INTERVAL);
+    }
+}
+
+/// Sends a finished message with `finish`, applying the overflow policy
+pub(crate) fn send<F>(mut finish: F) -> Result<()>
+    where F: FnMut() -> DltReturnValue
+{
+    // Only a full buffer loses the message, `DLT_RETURN_PIPE_FULL` means it was buffered
+    let mut ret = finish();
+
+    if ret == DltReturnValue::DLT_RETURN_BUFFER_FULL && POLICY.load(Ordering::Relaxed) == POLICY_BLOCK {
+        let timeout  = Duration::from_millis(BLOCK_TIMEOUT_MS.load(Ordering::Relaxed) as u64);
+        let deadline = Instant::now() + timeout;
+        while ret == DltReturnValue::DLT_RETURN_BUFFER_FULL && Instant::now() < deadline {
+            let _ = resend_buffer();
+            thread::sleep(RETRY_INTERVAL);
+            ret = finish();
+        }
+    }
+
+    match ret {
+        DltReturnValue::DLT_RETURN_PIPE_FULL   => Ok(()),
+        DltReturnValue::DLT_RETURN_BUFFER_FULL => {
+            LOST.fetch_add(1, Ordering::Relaxed);
+            check(ret)
+        },
+        _ => check(ret)
+    }
+}
+
+#[test]
+fn full_buffer_blocks_or_drops_the_message() {
+    let mut attempts = 0;
+    set_overflow_policy(OverflowPolicy::Block(Duration::from_millis(20))).unwrap();
+    let started = Instant::now();
+    assert!(send(|| { attempts += 1; DltReturnValue::DLT_RETURN_BUFFER_FULL }).is_err());
+    assert!(started.elapsed() >= Duration::from_millis(20));
+    assert!(attempts > 1);
+
+    let lost = LOST.load(Ordering::Relaxed);
+    attempts = 0;
+    set_overflow_policy(OverflowPolicy::DropNewest).unwrap();
+    assert!(send(|| { attempts += 1; DltReturnValue::DLT_RETURN_BUFFER_FULL }).is_err());
+    assert_eq!((attempts, LOST.load(Ordering::Relaxed)), (1, lost + 1));
+    assert!(send(|| DltReturnValue::DLT_RETURN_PIPE_FULL).is_ok());
+}
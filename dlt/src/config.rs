@@ -0,0 +1,232 @@
+//! Options of the user library, applied in one go by `init_with_config`.
+//!
+//! The user library only offers separate toggles(`dlt_with_session_id`, `dlt_verbose_mode`,
+//! ...) that nothing can be read back from, so the configuration applied last is kept
+//! here for diagnostics.
+
+use std::sync::{ Mutex, MutexGuard };
+use std::time::Duration;
+
+use ffi::{ self, DltUserLogMode };
+use buffer::set_resend_timeout;
+use shutdown::DEFAULT_FLUSH_DEADLINE;
+use { check, Error, Result };
+
+/// Where the daemon sends the messages of all the applications
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogMode {
+    /// Messages are dropped
+    Off,
+    /// Messages go to the connected clients
+    External,
+    /// Messages are stored by the daemon
+    Internal,
+    /// Both `External` and `Internal`
+    Both
+}
+
+impl LogMode {
+    fn to_ffi(self) -> DltUserLogMode {
+        match self {
+            LogMode::Off      => DltUserLogMode::DLT_USER_MODE_OFF,
+            LogMode::External => DltUserLogMode::DLT_USER_MODE_EXTERNAL,
+            LogMode::Internal => DltUserLogMode::DLT_USER_MODE_INTERNAL,
+            LogMode::Both     => DltUserLogMode::DLT_USER_MODE_BOTH
+        }
+    }
+}
+
+/// Header options, log mode and timeouts of the user library.
+///
+/// The defaults are the ones of libdlt: every optional header field, verbose messages, the
+/// log mode of the daemon left alone and local print while no client is connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    session_id: bool,
+    timestamp: bool,
+    ecu_id: bool,
+    extended_header_for_non_verbose: bool,
+    verbose: bool,
+    log_mode: Option<LogMode>,
+    local_print: bool,
+    resend_timeout: Duration
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            session_id: true,
+            timestamp: true,
+            ecu_id: true,
+            extended_header_for_non_verbose: true,
+            verbose: true,
+            log_mode: None,
+            local_print: true,
+            resend_timeout: DEFAULT_FLUSH_DEADLINE
+        }
+    }
+}
+
+impl Config {
+    /// The libdlt defaults
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Whether the messages carry the session ID(the process ID)
+    pub fn with_session_id(mut self, enable: bool) -> Config {
+        self.session_id = enable;
+        self
+    }
+
+    /// Whether the messages carry a timestamp
+    pub fn with_timestamp(mut self, enable: bool) -> Config {
+        self.timestamp = enable;
+        self
+    }
+
+    /// Whether the messages carry the ECU ID
+    pub fn with_ecu_id(mut self, enable: bool) -> Config {
+        self.ecu_id = enable;
+        self
+    }
+
+    /// Whether non-verbose messages carry the extended header(application and context IDs,
+    /// level). Verbose messages always do.
+    pub fn with_extended_header_for_non_verbose(mut self, enable: bool) -> Config {
+        self.extended_header_for_non_verbose = enable;
+        self
+    }
+
+    /// Verbose(self-describing arguments) or non-verbose messages
+    pub fn with_verbose(mut self, verbose: bool) -> Config {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sends a log mode to the daemon, which needs to be reachable
+    pub fn with_log_mode(mut self, mode: LogMode) -> Config {
+        self.log_mode = Some(mode);
+        self
+    }
+
+    /// Whether the user library prints the messages on stdout while no client is connected
+    pub fn with_local_print(mut self, enable: bool) -> Config {
+        self.local_print = enable;
+        self
+    }
+
+    /// How long the buffered messages may take to reach the daemon when the process ends
+    pub fn with_resend_timeout(mut self, timeout: Duration) -> Config {
+        self.resend_timeout = timeout;
+        self
+    }
+
+    pub fn session_id(&self) -> bool {
+        self.session_id
+    }
+
+    pub fn timestamp(&self) -> bool {
+        self.timestamp
+    }
+
+    pub fn ecu_id(&self) -> bool {
+        self.ecu_id
+    }
+
+    pub fn extended_header_for_non_verbose(&self) -> bool {
+        self.extended_header_for_non_verbose
+    }
+
+    pub fn verbose(&self) -> bool {
+        self.verbose
+    }
+
+    /// `None` when the log mode of the daemon is left alone
+    pub fn log_mode(&self) -> Option<LogMode> {
+        self.log_mode
+    }
+
+    pub fn local_print(&self) -> bool {
+        self.local_print
+    }
+
+    pub fn resend_timeout(&self) -> Duration {
+        self.resend_timeout
+    }
+
+    /// Checks the options go together
+    pub fn validate(&self) -> Result<()> {
+        if self.verbose && !self.extended_header_for_non_verbose {
+            return Err(Error::InvalidConfig("leaving out the extended header requires non-verbose mode".to_owned()));
+        }
+        if self.resend_timeout.as_millis() > u32::MAX as u128 {
+            return Err(Error::InvalidConfig(format!("resend timeout of {:?} is too long", self.resend_timeout)));
+        }
+
+        Ok(())
+    }
+
+    /// Validates and sets all the options of the user library
+    pub(crate) fn apply(&self) -> Result<()> {
+        self.validate()?;
+
+        unsafe {
+            check(ffi::dlt_with_session_id(self.session_id as i8))?;
+            check(ffi::dlt_with_timestamp(self.timestamp as i8))?;
+            check(ffi::dlt_with_ecu_id(self.ecu_id as i8))?;
+            check(ffi::dlt_use_extended_header_for_non_verbose(self.extended_header_for_non_verbose as i8))?;
+            check(if self.verbose { ffi::dlt_verbose_mode() } else { ffi::dlt_nonverbose_mode() })?;
+            check(if self.local_print { ffi::dlt_enable_local_print() } else { ffi::dlt_disable_local_print() })?;
+            if let Some(mode) = self.log_mode {
+                check(ffi::dlt_set_log_mode(mode.to_ffi()))?;
+            }
+        }
+        set_resend_timeout(self.resend_timeout);
+
+        *lock() = Some(self.clone());
+        Ok(())
+    }
+}
+
+/// Configuration applied last, `None` until `init_with_config`
+static EFFECTIVE: Mutex<Option<Config>> = Mutex::new(None);
+
+fn lock() -> MutexGuard<'static, Option<Config>> {
+    match EFFECTIVE.lock() {
+        Ok(config)    => config,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Applies the configuration of `init_with_config` again, after libdlt is re-initialized
+#[cfg(not(feature = "native"))]
+pub(crate) fn apply_again() -> Result<()> {
+    let applied = lock().clone();
+    match applied {
+        Some(config) => config.apply(),
+        None         => Ok(())
+    }
+}
+
+/// The configuration in effect, the defaults when `init_with_config` wasn't used
+pub fn effective_config() -> Config {
+    lock().clone().unwrap_or_default()
+}
+
+/// Whether the daemon reported a connected client, `None` until it did
+pub fn client_connected() -> Option<bool> {
+    match unsafe { ffi::dlt_get_log_state() } {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None
+    }
+}
+
+#[test]
+fn conflicting_options_are_rejected() {
+    assert!(Config::new().validate().is_ok());
+    assert!(Config::new().with_extended_header_for_non_verbose(false).validate().is_err());
+    assert!(Config::new().with_verbose(false).with_extended_header_for_non_verbose(false).validate().is_ok());
+    assert!(Config::new().with_resend_timeout(Duration::from_secs(5_000_000)).validate().is_err());
+}
@@ -0,0 +1,222 @@
+//! Mapping of Rust targets(module paths) to DLT contexts.
+//!
+//! Context IDs have only 4 characters, truncating module paths makes most of a code base
+//! collide. A target is resolved, in order, with:
+//!
+//! 1. the explicit table, matching the whole target
+//! 2. the longest prefix rule, matching whole path segments
+//! 3. a hash of the target, re-hashed until it doesn't collide with another target or rule
+//!
+//! Contexts are registered the first time one of their targets logs, and kept until the
+//! map is dropped.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::{ Mutex, MutexGuard };
+
+use { Context, Error, Level, Result, ID_SIZE };
+
+/// Characters of the hashed IDs, base32 without the ambiguous `0`, `1`, `8` and `9`
+const HASH_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Attempts to find a free hashed ID before giving up on a target
+const MAX_HASH_ATTEMPTS: u64 = 64;
+
+#[derive(Debug, Clone)]
+struct Mapping {
+    id: String,
+    description: String
+}
+
+#[derive(Default)]
+struct Cache {
+    // Resolved context ID, by target
+    ids: HashMap<String, String>,
+    // Target owning each hashed ID
+    hashed: HashMap<String, String>,
+    // Registered contexts, by context ID
+    contexts: HashMap<String, Context>
+}
+
+/// Called with each newly registered context
+type RegisterHook = Box<dyn Fn(&Context) + Send + Sync>;
+
+/// Resolves targets to context IDs and registers the contexts lazily
+#[derive(Default)]
+pub struct ContextMap {
+    table: HashMap<String, Mapping>,
+    prefixes: Vec<(String, Mapping)>,
+    cache: Mutex<Cache>,
+    on_register: Option<RegisterHook>
+}
+
+fn validate(id: &str) -> Result<()> {
+    if id.is_empty() || id.len() > ID_SIZE || !id.is_ascii() {
+        return Err(Error::InvalidId(id.to_owned()));
+    }
+
+    Ok(())
+}
+
+impl ContextMap {
+    /// Empty map, every target gets a hashed ID
+    pub fn new() -> ContextMap {
+        ContextMap::default()
+    }
+
+    /// Maps exactly `target` to the context `id`
+    pub fn target(mut self, target: &str, id: &str, description: &str) -> Result<ContextMap> {
+        validate(id)?;
+        self.table.insert(target.to_owned(), Mapping { id: id.to_owned(), description: description.to_owned() });
+        Ok(self)
+    }
+
+    /// Maps `prefix` and all the module paths below it to the context `id`
+    pub fn prefix(mut self, prefix: &str, id: &str, description: &str) -> Result<ContextMap> {
+        validate(id)?;
+        self.prefixes.retain(|(existing, _)| existing != prefix);
+        self.prefixes.push((prefix.to_owned(), Mapping { id: id.to_owned(), description: description.to_owned() }));
+        // Longest prefixes first, the first match wins
+        self.prefixes.sort_by_key(|(existing, _)| Reverse(existing.len()));
+        Ok(self)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cache> {
+        match self.cache.lock() {
+            Ok(cache)     => cache,
+            Err(poisoned) => poisoned.into_inner()
+        }
+    }
+
+    fn configured(&self, target: &str) -> Option<&Mapping> {
+        if let Some(mapping) = self.table.get(target) {
+            return Some(mapping);
+        }
+
+        self.prefixes.iter()
+            .find(|(prefix, _)| {
+                target == prefix || (target.starts_with(prefix.as_str()) && target[prefix.len()..].starts_with("::"))
+            })
+            .map(|(_, mapping)| mapping)
+    }
+
+    fn is_configured_id(&self, id: &str) -> bool {
+        self.table.values().chain(self.prefixes.iter().map(|(_, mapping)| mapping)).any(|mapping| mapping.id == id)
+    }
+
+    fn resolve(&self, cache: &mut Cache, target: &str) -> Result<String> {
+        if let Some(id) = cache.ids.get(target) {
+            return Ok(id.clone());
+        }
+
+        let id = match self.configured(target) {
+            Some(mapping) => mapping.id.clone(),
+            None          => {
+                let mut found = None;
+                for salt in 0..MAX_HASH_ATTEMPTS {
+                    let id = hashed_id(target, salt);
+                    let taken = self.is_configured_id(&id) ||
+                                cache.hashed.get(&id).is_some_and(|owner| owner != target);
+                    if !taken {
+                        found = Some(id);
+                        break;
+                    }
+                }
+
+                let id = found.ok_or_else(|| Error::InvalidId(target.to_owned()))?;
+                cache.hashed.insert(id.clone(), target.to_owned());
+                id
+            }
+        };
+
+        cache.ids.insert(target.to_owned(), id.clone());
+        Ok(id)
+    }
+
+    /// The context ID `target` maps to
+    pub fn context_id(&self, target: &str) -> Result<String> {
+        let mut cache = self.lock();
+        self.resolve(&mut cache, target)
+    }
+
+    /// The context of `target`, registered on first use.
+    ///
+    /// The description is the configured one, or the target itself for hashed IDs.
+    pub fn context(&self, target: &str) -> Result<Context> {
+        let mut cache = self.lock();
+        let id = self.resolve(&mut cache, target)?;
+        if let Some(context) = cache.contexts.get(&id) {
+            return Ok(context.clone());
+        }
+
+        let description = match self.configured(target) {
+            Some(mapping) => mapping.description.as_str(),
+            None          => target
+        };
+        let context = Context::register(&id, description)?;
+        cache.contexts.insert(id, context.clone());
+        drop(cache);
+
+        if let Some(ref on_register) = self.on_register {
+            on_register(&context);
+        }
+        Ok(context)
+    }
+
+    /// Called with each context once registered, without holding the lock of the map
+    #[cfg(feature = "log")]
+    pub(crate) fn set_on_register(&mut self, on_register: RegisterHook) {
+        self.on_register = Some(on_register);
+    }
+
+    /// The registered contexts and their current level, sorted by context ID
+    pub fn levels(&self) -> Vec<(String, Option<Level>)> {
+        let cache = self.lock();
+        let mut levels: Vec<(String, Option<Level>)> = cache.contexts.iter()
+                                                                     .map(|(id, context)| (id.clone(), context.level()))
+                                                                     .collect();
+        levels.sort();
+        levels
+    }
+
+    /// The resolved mappings so far, sorted by target
+    pub fn mappings(&self) -> Vec<(String, String)> {
+        let cache = self.lock();
+        let mut mappings: Vec<(String, String)> = cache.ids.iter()
+                                                            .map(|(target, id)| (target.clone(), id.clone()))
+                                                            .collect();
+        mappings.sort();
+        mappings
+    }
+}
+
+/// FNV-1a of the target and salt, spelled with `ID_SIZE` characters of `HASH_ALPHABET`
+fn hashed_id(target: &str, salt: u64) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in target.as_bytes().iter().chain(salt.to_le_bytes().iter()) {
+        hash ^= byte as u64;
+        hash  = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    (0..ID_SIZE).map(|i| HASH_ALPHABET[(hash >> (i * 5)) as usize % HASH_ALPHABET.len()] as char).collect()
+}
+
+#[test]
+fn targets_resolve_by_table_prefix_then_hash() {
+    let map = ContextMap::new().prefix("service::net", "NET", "Networking").unwrap()
+                               .prefix("service::net::http", "HTTP", "HTTP server").unwrap()
+                               .target("service::net::dns", "DNS", "Resolver").unwrap();
+
+    assert_eq!(map.context_id("service::net").unwrap(), "NET");
+    assert_eq!(map.context_id("service::net::tcp").unwrap(), "NET");
+    assert_eq!(map.context_id("service::net::http::router").unwrap(), "HTTP");
+    assert_eq!(map.context_id("service::net::dns").unwrap(), "DNS");
+    assert_eq!(map.context_id("service::network").unwrap(), hashed_id("service::network", 0));
+
+    let first  = map.context_id("service::storage::a").unwrap();
+    let second = map.context_id("service::storage::b").unwrap();
+    assert_eq!(first.len(), ID_SIZE);
+    assert_ne!(first, second);
+    assert_eq!(map.context_id("service::storage::a").unwrap(), first);
+    assert!(ContextMap::new().prefix("service", "TOOLONG", "").is_err());
+}
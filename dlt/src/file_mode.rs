@@ -0,0 +1,591 @@
+//! Writing the messages to a `.dlt` file instead of the daemon, for machines without one.
+//!
+//! The file is truncated when opened and every message is stored with its storage header,
+//! so it can be opened by any DLT viewer even when the process dies:
+//!
+//! ```no_run
+//! dlt::init_to_file("/tmp/bench.dlt").unwrap();
+//! let _dlt = dlt::init("RLOG", "Rust logging").unwrap();
+//! ```
+//!
+//! libdlt only offers `dlt_init_file`, with it the crate rotates and syncs the file libdlt
+//! writes, through the descriptor it opened, before and after each message of a `Context`.
+//! Messages other code of the process logs through libdlt itself may grow the file past the
+//! maximum size, until the next one of the crate. Switching between the daemon and the file
+//! once an application is registered frees libdlt(`dlt_free`) and registers the application
+//! and the contexts again, the threads logging meanwhile wait for it.
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, Condvar, Mutex, MutexGuard, Weak };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::time::Duration;
+#[cfg(not(feature = "native"))]
+use std::fs::{ self, File };
+#[cfg(not(feature = "native"))]
+use std::io;
+#[cfg(not(feature = "native"))]
+use std::{ mem, ptr };
+#[cfg(not(feature = "native"))]
+use std::os::unix::fs::MetadataExt;
+#[cfg(not(feature = "native"))]
+use std::os::unix::io::{ AsRawFd, RawFd };
+#[cfg(not(feature = "native"))]
+use std::time::Instant;
+
+use ffi;
+use { check, ContextInner, Result };
+#[cfg(not(feature = "native"))]
+use libc::c_int;
+#[cfg(not(feature = "native"))]
+use { app_description, app_id, buffer, config, id_to_cstring, level_changed, level_limit, protocol, Error,
+      DEFAULT_FLUSH_DEADLINE, TRACE_STATUS_NOT_SET };
+
+/// When the file is synced to disk
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Left to the operating system, what libdlt does
+    Never,
+    /// After every message, nothing is lost on power failure
+    EveryMessage,
+    /// After the first message written once the interval elapsed
+    Interval(Duration)
+}
+
+/// Size, rotation and sync of the log file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FileOptions {
+    max_size: u64,
+    max_files: u32,
+    sync: SyncPolicy
+}
+
+impl Default for FileOptions {
+    fn default() -> FileOptions {
+        FileOptions { max_size: 0, max_files: 0, sync: SyncPolicy::Never }
+    }
+}
+
+impl FileOptions {
+    /// A single file growing without limit, never synced
+    pub fn new() -> FileOptions {
+        FileOptions::default()
+    }
+
+    /// Rotates the file before it grows past `max_size` bytes, `0` for no limit
+    pub fn with_max_size(mut self, max_size: u64) -> FileOptions {
+        self.max_size = max_size;
+        self
+    }
+
+    /// How many rotated files(`trace.1.dlt` being the newest) are kept, `0` starts the
+    /// file over instead
+    pub fn with_max_files(mut self, max_files: u32) -> FileOptions {
+        self.max_files = max_files;
+        self
+    }
+
+    /// When the file is synced to disk, `SyncPolicy::Never` by default
+    pub fn with_sync(mut self, sync: SyncPolicy) -> FileOptions {
+        self.sync = sync;
+        self
+    }
+}
+
+static TO_FILE: AtomicBool = AtomicBool::new(false);
+
+/// Writes the log messages to `path`, see `init_to_file_with`
+pub fn init_to_file<P: AsRef<Path>>(path: P) -> Result<()> {
+    init_to_file_with(path, &FileOptions::new())
+}
+
+/// Writes the log messages to `path` instead of the daemon, before or after `init`
+pub fn init_to_file_with<P: AsRef<Path>>(path: P, options: &FileOptions) -> Result<()> {
+    let path = path.as_ref();
+    open(path, options)?;
+
+    TO_FILE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Closes the log file, the following messages go to the daemon
+pub fn switch_to_daemon() -> Result<()> {
+    close()?;
+
+    TO_FILE.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether the messages are written to a file
+pub fn logging_to_file() -> bool {
+    TO_FILE.load(Ordering::Relaxed)
+}
+
+/// `trace.dlt` rotated for the `index`th time is `trace.<index>.dlt`
+pub(crate) fn rotated(path: &Path, index: u32) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}.{}.{}", stem, index, extension.to_string_lossy())),
+        None            => path.with_file_name(format!("{}.{}", stem, index))
+    }
+}
+
+#[cfg(feature = "native")]
+fn open(path: &Path, options: &FileOptions) -> Result<()> {
+    ffi::dlt_user_set_file_options(ffi::SinkOptions {
+        max_size: options.max_size,
+        max_files: options.max_files,
+        sync_interval: match options.sync {
+            SyncPolicy::Never              => None,
+            SyncPolicy::EveryMessage       => Some(Duration::from_secs(0)),
+            SyncPolicy::Interval(interval) => Some(interval)
+        }
+    });
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    check(unsafe { ffi::dlt_init_file(c_path.as_ptr()) })
+}
+
+#[cfg(not(feature = "native"))]
+fn open(path: &Path, options: &FileOptions) -> Result<()> {
+    // libdlt doesn't truncate the file, the end of a longer previous one would follow the messages
+    let created = File::create(path)?.metadata()?;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    reinit(|| check(unsafe { ffi::dlt_init_file(c_path.as_ptr()) }))?;
+
+    *log_file() = Some(LogFile {
+        path: path.to_owned(),
+        fd: find_fd(&created)?,
+        options: *options,
+        last_sync: Instant::now()
+    });
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn close() -> Result<()> {
+    check(ffi::dlt_user_close_file())
+}
+
+#[cfg(not(feature = "native"))]
+fn close() -> Result<()> {
+    if let Some(mut file) = log_file().take() {
+        file.sync()?;
+    }
+
+    // libdlt connects to the daemon again when the application is registered
+    reinit(|| Ok(()))
+}
+
+/// Sends a message of `size` bytes of payload with `send`, rotating and syncing the log file
+#[cfg(feature = "native")]
+pub(crate) fn write<F>(_size: usize, send: F) -> Result<()>
+    where F: FnOnce() -> Result<()>
+{
+    // The user library does both
+    send()
+}
+
+/// Sends a message of `size` bytes of payload with `send`, rotating and syncing the log file
+#[cfg(not(feature = "native"))]
+pub(crate) fn write<F>(size: usize, send: F) -> Result<()>
+    where F: FnOnce() -> Result<()>
+{
+    if !logging_to_file() {
+        return send();
+    }
+
+    // Held while sending, the file isn't rotated in the middle of a message
+    let mut log_file = log_file();
+    match *log_file {
+        Some(ref mut file) => {
+            file.make_room(size)?;
+            send()?;
+            Ok(file.written()?)
+        },
+        None => send()
+    }
+}
+
+/// Storage header, then the largest standard(with ECU ID, session ID and timestamp) and
+/// extended headers libdlt writes before the payload
+#[cfg(not(feature = "native"))]
+const MAX_HEADERS_SIZE: usize = protocol::STORAGE_HEADER_SIZE + 16 + 10;
+
+/// The file libdlt writes to, through its own descriptor
+#[cfg(not(feature = "native"))]
+struct LogFile {
+    path: PathBuf,
+    fd: RawFd,
+    options: FileOptions,
+    last_sync: Instant
+}
+
+#[cfg(not(feature = "native"))]
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+#[cfg(not(feature = "native"))]
+fn log_file() -> MutexGuard<'static, Option<LogFile>> {
+    match LOG_FILE.lock() {
+        Ok(log_file)  => log_file,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+#[cfg(not(feature = "native"))]
+impl LogFile {
+    /// Rotates the file if a message of `size` bytes of payload could grow it past the maximum
+    fn make_room(&mut self, size: usize) -> io::Result<()> {
+        if self.options.max_size == 0 {
+            return Ok(());
+        }
+
+        // Read from the file, libdlt writes the messages of the C code of the process too
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(self.fd, &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let current = stat.st_size as u64;
+        if current > 0 && current + (MAX_HEADERS_SIZE + size) as u64 > self.options.max_size {
+            self.rotate()?;
+        }
+
+        Ok(())
+    }
+
+    fn written(&mut self) -> io::Result<()> {
+        match self.options.sync {
+            SyncPolicy::EveryMessage                                        => self.sync(),
+            SyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => self.sync(),
+            _ => Ok(())
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.last_sync = Instant::now();
+        if unsafe { libc::fdatasync(self.fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Shifts the rotated files and puts a new file behind the descriptor of libdlt
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+
+        let max_files = self.options.max_files;
+        if max_files > 0 {
+            let _ = fs::remove_file(rotated(&self.path, max_files));
+            for index in (1..max_files).rev() {
+                let _ = fs::rename(rotated(&self.path, index), rotated(&self.path, index + 1));
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+
+        // Also resets the offset libdlt writes at, when the file is started over
+        let file = File::create(&self.path)?;
+        if unsafe { libc::dup2(file.as_raw_fd(), self.fd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+/// The descriptor libdlt opened the file `created` with, it is kept in its private state
+#[cfg(not(feature = "native"))]
+fn find_fd(created: &fs::Metadata) -> Result<RawFd> {
+    for entry in fs::read_dir("/proc/self/fd")? {
+        let entry = entry?;
+        let fd = match entry.file_name().to_str().and_then(|fd| fd.parse().ok()) {
+            Some(fd) => fd,
+            None     => continue
+        };
+
+        match fs::metadata(entry.path()) {
+            Ok(opened) if opened.dev() == created.dev() && opened.ino() == created.ino() => return Ok(fd),
+            _ => {}
+        }
+    }
+
+    Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "libdlt didn't open the log file")))
+}
+
+/// Threads using the context handles, and whether libdlt is being re-initialized
+struct Users {
+    count: usize,
+    reinit: bool
+}
+
+static USERS: Mutex<Users>   = Mutex::new(Users { count: 0, reinit: false });
+static USERS_CHANGED: Condvar = Condvar::new();
+
+/// Bumped each time libdlt is re-initialized, the handles of older contexts are gone
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Uses of the context handles by this thread, only the first one waits
+    static USING: Cell<usize> = const { Cell::new(0) };
+}
+
+fn users() -> MutexGuard<'static, Users> {
+    match USERS.lock() {
+        Ok(users)     => users,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+fn wait(users: MutexGuard<'static, Users>) -> MutexGuard<'static, Users> {
+    match USERS_CHANGED.wait(users) {
+        Ok(users)     => users,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Use of the context handles by the current thread, libdlt isn't re-initialized until it is
+/// dropped
+pub(crate) struct Shared {
+    // Dropped on the thread that counted it
+    _thread: PhantomData<*const ()>
+}
+
+/// Waits until libdlt isn't being re-initialized, a thread already using the handles doesn't
+pub(crate) fn shared() -> Shared {
+    if USING.with(Cell::get) == 0 {
+        let mut users = users();
+        while users.reinit {
+            users = wait(users);
+        }
+        users.count += 1;
+    }
+
+    USING.with(|using| using.set(using.get() + 1));
+    Shared { _thread: PhantomData }
+}
+
+/// Like `shared`, without waiting.
+///
+/// ### Returns
+/// `None` while libdlt is being re-initialized
+pub(crate) fn try_shared() -> Option<Shared> {
+    if USING.with(Cell::get) == 0 {
+        let mut users = users();
+        if users.reinit {
+            return None;
+        }
+        users.count += 1;
+    }
+
+    USING.with(|using| using.set(using.get() + 1));
+    Some(Shared { _thread: PhantomData })
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let using = USING.with(|using| {
+            using.set(using.get() - 1);
+            using.get()
+        });
+
+        if using == 0 {
+            let mut users = users();
+            users.count -= 1;
+            if users.count == 0 {
+                USERS_CHANGED.notify_all();
+            }
+        }
+    }
+}
+
+/// The current generation, see `GENERATION`
+pub(crate) fn generation() -> usize {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Handles(`*mut DltContext`) of the registered contexts, registered again with libdlt
+/// after it is re-initialized
+static CONTEXTS: Mutex<Vec<(usize, Weak<ContextInner>)>> = Mutex::new(Vec::new());
+
+fn contexts() -> MutexGuard<'static, Vec<(usize, Weak<ContextInner>)>> {
+    match CONTEXTS.lock() {
+        Ok(contexts)  => contexts,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+pub(crate) fn add_context(context: &Arc<ContextInner>) {
+    contexts().push((context.handle.get() as usize, Arc::downgrade(context)));
+}
+
+pub(crate) fn remove_context(context: &ContextInner) {
+    contexts().retain(|&(handle, _)| handle != context.handle.get() as usize);
+}
+
+/// Exclusive use of the context handles, while libdlt is re-initialized
+#[cfg(not(feature = "native"))]
+struct Exclusive;
+
+#[cfg(not(feature = "native"))]
+fn exclusive() -> Exclusive {
+    let mut users = users();
+    while users.reinit {
+        users = wait(users);
+    }
+
+    users.reinit = true;
+    while users.count > 0 {
+        users = wait(users);
+    }
+
+    Exclusive
+}
+
+#[cfg(not(feature = "native"))]
+impl Drop for Exclusive {
+    fn drop(&mut self) {
+        users().reinit = false;
+        USERS_CHANGED.notify_all();
+    }
+}
+
+/// `DLT_USER_LOG_LEVEL_NOT_SET`, the level comes from the daemon or the defaults
+#[cfg(not(feature = "native"))]
+const LOG_LEVEL_NOT_SET: c_int = -2;
+
+/// Frees libdlt and initializes it again with `init`, then registers the application and the
+/// contexts again: libdlt doesn't switch between the daemon and a file otherwise
+#[cfg(not(feature = "native"))]
+fn reinit<F>(init: F) -> Result<()>
+    where F: FnOnce() -> Result<()>
+{
+    if USING.with(Cell::get) > 0 {
+        return Err(Error::Unsupported("switching between the daemon and a file while the thread is logging"));
+    }
+
+    // `dlt_free` drops the buffered messages
+    let registered = !app_id().is_empty();
+    if registered {
+        let _ = buffer::flush_buffer(DEFAULT_FLUSH_DEADLINE);
+    }
+
+    let exclusive = exclusive();
+    let contexts = {
+        let mut alive = Vec::new();
+        // Those being dropped are unregistered by `dlt_free`, their handles are forgotten
+        contexts().retain(|&(handle, ref context)| match context.upgrade() {
+            Some(context) => {
+                alive.push(context);
+                true
+            },
+            None => {
+                level_changed::remove(handle as *mut ffi::DltContext);
+                level_limit::remove_context(handle as *mut ffi::DltContext);
+                false
+            }
+        });
+        alive
+    };
+    let result = register_again(&contexts, init, registered);
+
+    // Dropping the last clone of a context unregisters it, which needs the handles
+    drop(exclusive);
+    result
+}
+
+#[cfg(not(feature = "native"))]
+fn register_again<F>(contexts: &[Arc<ContextInner>], init: F, registered: bool) -> Result<()>
+    where F: FnOnce() -> Result<()>
+{
+    // Raised or set by the daemon, the levels are kept
+    let levels: Vec<(c_int, c_int)> = contexts.iter().map(|context| unsafe {
+        let handle = &*context.handle.get();
+        if handle.log_level_ptr.is_null() || handle.trace_status_ptr.is_null() {
+            (LOG_LEVEL_NOT_SET, TRACE_STATUS_NOT_SET)
+        } else {
+            (c_int::from(*handle.log_level_ptr), c_int::from(*handle.trace_status_ptr))
+        }
+    }).collect();
+
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    unsafe {
+        // Returns an error if libdlt wasn't initialized
+        ffi::dlt_free();
+        for context in contexts {
+            let handle = &mut *context.handle.get();
+            handle.log_level_ptr    = ptr::null_mut();
+            handle.trace_status_ptr = ptr::null_mut();
+        }
+    }
+
+    // Without a file libdlt goes back to the daemon, the registrations go on
+    let mut result = init();
+    if registered {
+        let c_id          = id_to_cstring(&app_id())?;
+        let c_description = CString::new(app_description().as_str())?;
+        result = result.and(check(unsafe { ffi::dlt_register_app(c_id.as_ptr(), c_description.as_ptr()) }));
+        // The options of `init_with_config` are reset too
+        result = result.and(config::apply_again());
+    }
+
+    for (context, &(level, trace_status)) in contexts.iter().zip(&levels) {
+        let c_id          = id_to_cstring(&context.id)?;
+        let c_description = CString::new(context.description.as_str())?;
+        result = result.and(check(unsafe {
+            ffi::dlt_register_context_ll_ts(context.handle.get(), c_id.as_ptr(), c_description.as_ptr(),
+                                            level, trace_status)
+        }));
+        context.generation.store(generation(), Ordering::Relaxed);
+        result = result.and(level_changed::register_again(context.handle.get()));
+    }
+
+    result
+}
+
+#[cfg(not(feature = "native"))]
+#[test]
+fn libdlt_file_is_rotated_then_left() {
+    use protocol::{ parse_stored_record, Parsed };
+    use { Application, Context, Level };
+
+    let dir  = ::std::env::temp_dir().join(format!("dlt-file-mode-{}", ::std::process::id()));
+    let path = dir.join("trace.dlt");
+    fs::create_dir_all(&dir).unwrap();
+
+    let options = FileOptions::new().with_max_size(1024).with_max_files(2).with_sync(SyncPolicy::EveryMessage);
+    init_to_file_with(&path, &options).unwrap();
+    let app     = Application::register("RLOG", "Rust logging").unwrap();
+    let context = Context::register("FILE", "File mode").unwrap();
+    for index in 0..100 {
+        context.log(Level::Info, &format!("message {}", index)).unwrap();
+    }
+
+    for file in &[path.clone(), rotated(&path, 1), rotated(&path, 2)] {
+        let stored = fs::read(file).unwrap();
+        assert!(!stored.is_empty() && stored.len() <= 1024, "{} bytes in {:?}", stored.len(), file);
+
+        let mut rest = &stored[..];
+        while !rest.is_empty() {
+            match parse_stored_record(rest) {
+                Parsed::Complete(_, size) => rest = &rest[size..],
+                other                     => panic!("unexpected parse result in {:?}: {:?}", file, other)
+            }
+        }
+    }
+    assert!(!rotated(&path, 3).exists());
+
+    // Without a daemon the messages stay in the buffer of libdlt
+    switch_to_daemon().unwrap();
+    let size = fs::metadata(&path).unwrap().len();
+    assert_eq!(context.level(), Some(Level::Info));
+    context.log(Level::Info, "to the daemon").unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), size);
+
+    drop((context, app));
+    fs::remove_dir_all(&dir).unwrap();
+}
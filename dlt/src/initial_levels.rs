@@ -0,0 +1,280 @@
+//! Initial log levels of the contexts, configured per deployment.
+//!
+//! The rules use the syntax of libdlt's `DLT_INITIAL_LOG_LEVEL`: `APID:CTID:LL` items separated
+//! by `;`, an empty ID matching any application or context and `LL` going from `0`(off) to
+//! `6`(verbose), `-1` leaving the level to the daemon. On top of it, IDs may be patterns(`*`
+//! and `?`), levels may be named(`off`, `fatal`, ..., `verbose`) and, with the `toml`
+//! feature, rules may come from a file:
+//!
+//! ```toml
+//! [[rule]]
+//! app     = "RLOG"    # any application when missing
+//! context = "NET*"    # any context when missing
+//! level   = "debug"   # or 5
+//! ```
+//!
+//! The most specific matching rule wins, like in libdlt: an exact application ID weighs more
+//! than an exact context ID, patterns weigh less than exact IDs and more than empty ones.
+//! Between equally specific rules, the first one wins.
+
+use std::env;
+use std::io::{ self, Write };
+use std::sync::{ Mutex, MutexGuard };
+
+use { Error, Level, Result };
+
+/// Variable read when no configuration was set with `set_initial_levels`
+pub const INITIAL_LOG_LEVEL_VAR: &str = "DLT_INITIAL_LOG_LEVEL";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Any,
+    Exact(String),
+    Glob(String)
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Pattern {
+        if pattern.is_empty() || pattern == "*" {
+            Pattern::Any
+        } else if pattern.contains(['*', '?']) {
+            Pattern::Glob(pattern.to_owned())
+        } else {
+            Pattern::Exact(pattern.to_owned())
+        }
+    }
+
+    /// How specific the match of `id` is, `None` if it doesn't match
+    fn score(&self, id: &str) -> Option<u8> {
+        match *self {
+            Pattern::Any                                                       => Some(0),
+            Pattern::Glob(ref glob) if matches(glob.as_bytes(), id.as_bytes()) => Some(1),
+            Pattern::Exact(ref exact) if exact == id                           => Some(2),
+            _                                                                  => None
+        }
+    }
+}
+
+/// Matches `id` against a pattern where `*` is any sequence and `?` any character
+fn matches(pattern: &[u8], id: &[u8]) -> bool {
+    match (pattern.first(), id.first()) {
+        (None, None)           => true,
+        (Some(&b'*'), _)       => matches(&pattern[1..], id) || (!id.is_empty() && matches(pattern, &id[1..])),
+        (Some(&b'?'), Some(_)) => matches(&pattern[1..], &id[1..]),
+        (Some(p), Some(c))     => p == c && matches(&pattern[1..], &id[1..]),
+        _                      => false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    app: Pattern,
+    context: Pattern,
+    level: Setting
+}
+
+/// Rules giving the initial log level of the contexts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelConfig {
+    rules: Vec<Rule>
+}
+
+fn invalid(what: &str, item: &str) -> Error {
+    Error::InvalidConfig(format!("{} in \"{}\"", what, item))
+}
+
+/// Level of a rule as written in the configuration
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Setting {
+    /// `-1`, the daemon decides: shadows the less specific rules
+    Default,
+    /// `None` turns logging off
+    Set(Option<Level>)
+}
+
+/// Parses a level number(`-1` to `6`) or name
+fn parse_level(level: &str) -> Option<Setting> {
+    let level = level.trim();
+    match level.parse::<i8>() {
+        Ok(-1)                   => Some(Setting::Default),
+        Ok(0)                    => Some(Setting::Set(None)),
+        Ok(raw) if raw > 0       => Level::from_raw(raw as u8).map(|level| Setting::Set(Some(level))),
+        Ok(_)                    => None,
+        Err(_) if level == "off" => Some(Setting::Set(None)),
+        Err(_)                   => {
+            (1..7).filter_map(Level::from_raw).find(|l| l.to_string() == level).map(|level| Setting::Set(Some(level)))
+        }
+    }
+}
+
+impl LevelConfig {
+    /// No rule, the daemon decides
+    pub fn new() -> LevelConfig {
+        LevelConfig::default()
+    }
+
+    /// Parses `APID:CTID:LL;...` rules
+    pub fn parse(rules: &str) -> Result<LevelConfig> {
+        let mut config = LevelConfig::new();
+        for item in rules.split(';').map(str::trim).filter(|item| !item.is_empty()) {
+            let fields: Vec<&str> = item.split(':').collect();
+            if fields.len() != 3 {
+                return Err(invalid("expected APID:CTID:LL", item));
+            }
+
+            let level = parse_level(fields[2]).ok_or_else(|| invalid("invalid log level", item))?;
+            config = config.push(fields[0].trim(), fields[1].trim(), level)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Parses the rules of `DLT_INITIAL_LOG_LEVEL`, none if it isn't set
+    pub fn from_env() -> Result<LevelConfig> {
+        match env::var(INITIAL_LOG_LEVEL_VAR) {
+            Ok(rules) => LevelConfig::parse(&rules),
+            Err(_)    => Ok(LevelConfig::new())
+        }
+    }
+
+    /// Parses the `[[rule]]` tables of a TOML document
+    #[cfg(feature = "toml")]
+    pub fn from_toml(document: &str) -> Result<LevelConfig> {
+        use toml::Value;
+
+        let document = document.parse::<Value>().map_err(|err| Error::InvalidConfig(err.to_string()))?;
+        let rules: &[Value] = match document.get("rule") {
+            Some(Value::Array(rules)) => rules,
+            Some(_)                   => return Err(Error::InvalidConfig("\"rule\" must be an array of tables".to_owned())),
+            None                      => &[]
+        };
+
+        let mut config = LevelConfig::new();
+        for rule in rules {
+            let id = |key: &str| match rule.get(key) {
+                Some(Value::String(id)) => Ok(id.clone()),
+                Some(_)                 => Err(invalid("expected a string", key)),
+                None                    => Ok(String::new())
+            };
+            let level = match rule.get("level") {
+                Some(Value::String(level))   => parse_level(level),
+                Some(&Value::Integer(level)) => parse_level(&level.to_string()),
+                _                            => None
+            };
+            let level = level.ok_or_else(|| invalid("invalid log level", &rule.to_string()))?;
+            config = config.push(&id("app")?, &id("context")?, level)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the rules of a TOML file
+    #[cfg(feature = "toml")]
+    pub fn from_toml_file<P: AsRef<::std::path::Path>>(path: P) -> Result<LevelConfig> {
+        let document = ::std::fs::read_to_string(path)?;
+        LevelConfig::from_toml(&document)
+    }
+
+    /// Adds a rule, `app` and `context` are IDs or patterns, empty for any. `None` turns
+    /// logging off.
+    pub fn rule(self, app: &str, context: &str, level: Option<Level>) -> Result<LevelConfig> {
+        self.push(app, context, Setting::Set(level))
+    }
+
+    fn push(mut self, app: &str, context: &str, level: Setting) -> Result<LevelConfig> {
+        for id in &[app, context] {
+            if !id.is_ascii() || id.contains([':', ';']) {
+                return Err(invalid("invalid ID pattern", id));
+            }
+        }
+
+        self.rules.push(Rule { app: Pattern::parse(app), context: Pattern::parse(context), level });
+        Ok(self)
+    }
+
+    /// Rules of `other` take precedence over the equally specific ones of `self`
+    pub fn merge(mut self, other: LevelConfig) -> LevelConfig {
+        let rules = self.rules;
+        self.rules = other.rules;
+        self.rules.extend(rules);
+        self
+    }
+
+    /// The initial level of the context `context_id` of `app_id`.
+    ///
+    /// ### Returns
+    /// `None` when no rule matches or the most specific one is `-1`, `Some(None)` when logging
+    /// is turned off
+    pub fn level(&self, app_id: &str, context_id: &str) -> Option<Option<Level>> {
+        let mut best: Option<((u8, u8), Setting)> = None;
+        for rule in &self.rules {
+            let score = match (rule.app.score(app_id), rule.context.score(context_id)) {
+                (Some(app), Some(context)) => (app, context),
+                _                          => continue
+            };
+
+            if best.is_none_or(|(best, _)| score > best) {
+                best = Some((score, rule.level));
+            }
+        }
+
+        match best {
+            Some((_, Setting::Set(level))) => Some(level),
+            _                              => None
+        }
+    }
+}
+
+// Read from the environment on first use
+static CONFIG: Mutex<Option<LevelConfig>> = Mutex::new(None);
+
+fn lock() -> MutexGuard<'static, Option<LevelConfig>> {
+    match CONFIG.lock() {
+        Ok(state)     => state,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Uses `config` for the contexts registered from now on, instead of `DLT_INITIAL_LOG_LEVEL`
+pub fn set_initial_levels(config: LevelConfig) {
+    *lock() = Some(config);
+}
+
+/// The initial level of the context `context_id` of `app_id`
+pub(crate) fn initial_level(app_id: &str, context_id: &str) -> Option<Option<Level>> {
+    let mut config = lock();
+    let config = config.get_or_insert_with(|| {
+        LevelConfig::from_env().unwrap_or_else(|err| {
+            let _ = writeln!(io::stderr(), "dlt: ignoring {}: {}", INITIAL_LOG_LEVEL_VAR, err);
+            LevelConfig::new()
+        })
+    });
+
+    config.level(app_id, context_id)
+}
+
+#[test]
+fn most_specific_rule_wins() {
+    let config = LevelConfig::parse("::2;:NET:3;RLOG::4;RLOG:NET:5;RL??:DB*:verbose;;OTHR:NET:-1").unwrap();
+
+    assert_eq!(config.level("APP1", "CTX1"), Some(Some(Level::Error)));
+    assert_eq!(config.level("APP1", "NET"), Some(Some(Level::Warn)));
+    assert_eq!(config.level("RLOG", "CTX1"), Some(Some(Level::Info)));
+    assert_eq!(config.level("RLOG", "NET"), Some(Some(Level::Debug)));
+    assert_eq!(config.level("RLAB", "DBPL"), Some(Some(Level::Verbose)));
+    assert_eq!(config.level("RLOG", "DBPL"), Some(Some(Level::Info)));
+    assert_eq!(config.level("OTHR", "NET"), None);
+    assert_eq!(LevelConfig::parse("APP1::0").unwrap().level("APP1", "CTX1"), Some(None));
+    assert_eq!(LevelConfig::new().level("APP1", "CTX1"), None);
+    assert!(LevelConfig::parse("RLOG:NET").is_err());
+    assert!(LevelConfig::parse("RLOG:NET:9").is_err());
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn rules_from_toml() {
+    let config = LevelConfig::from_toml("[[rule]]\nlevel = 2\n\n[[rule]]\napp = \"RLOG\"\ncontext = \"NET*\"\nlevel = \"debug\"\n").unwrap();
+
+    assert_eq!(config, LevelConfig::parse("::2;RLOG:NET*:5").unwrap());
+    assert!(LevelConfig::from_toml("[[rule]]\nlevel = \"loud\"\n").is_err());
+}
@@ -0,0 +1,84 @@
+//! Notifications of the log level changes made by the daemon.
+//!
+//! The user library takes a plain function pointer, without user data, so a single
+//! trampoline dispatches to the Rust callbacks by context ID. The callbacks are kept per
+//! context handle, unregistering a context leaves those of another one with the same ID.
+
+use std::slice;
+use std::sync::{ Arc, Mutex, MutexGuard };
+
+use libc::c_char;
+
+use ffi::{ self, DltContext };
+use { check, file_mode, id_to_string, Context, Level, Result, ID_SIZE };
+
+type Callback = Arc<dyn Fn(Option<Level>) + Send + Sync>;
+
+/// Handle(`*mut DltContext`) and ID of the context, and the callback
+type Registration = (usize, String, Callback);
+
+static CALLBACKS: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+fn lock() -> MutexGuard<'static, Vec<Registration>> {
+    match CALLBACKS.lock() {
+        Ok(callbacks) => callbacks,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+unsafe extern "C" fn level_changed(context_id: *mut c_char, log_level: u8, _trace_status: u8) {
+    if context_id.is_null() {
+        return;
+    }
+
+    // While libdlt is re-initialized, `dlt_free` waits for this thread to stop, a callback
+    // waiting for the handles would never return. The contexts are registered anew anyway.
+    let _shared = match file_mode::try_shared() {
+        Some(shared) => shared,
+        None         => return
+    };
+
+    let id = id_to_string(slice::from_raw_parts(context_id as *const u8, ID_SIZE));
+    let callbacks: Vec<Callback> = lock().iter()
+                                         .filter(|&(_, context, _)| *context == id)
+                                         .map(|(_, _, callback)| callback.clone())
+                                         .collect();
+
+    // Called without holding the lock, a callback may register another one
+    let level = Level::from_raw(log_level);
+    for callback in callbacks {
+        callback(level);
+    }
+}
+
+/// Adds `callback` to the ones of `context`, the user library calls them from its own thread
+pub fn register(context: &Context, callback: Callback) -> Result<()> {
+    let first = {
+        let handle = context.as_ptr() as usize;
+        let mut callbacks = lock();
+        let first = !callbacks.iter().any(|&(registered, _, _)| registered == handle);
+        callbacks.push((handle, context.id().to_owned(), callback));
+        first
+    };
+
+    if first {
+        check(unsafe { ffi::dlt_register_log_level_changed_callback(context.as_ptr(), Some(level_changed)) })?;
+    }
+
+    Ok(())
+}
+
+/// Registers the trampoline again for a context with callbacks, after libdlt is re-initialized
+#[cfg(not(feature = "native"))]
+pub fn register_again(handle: *mut DltContext) -> Result<()> {
+    if !lock().iter().any(|&(registered, _, _)| registered == handle as usize) {
+        return Ok(());
+    }
+
+    check(unsafe { ffi::dlt_register_log_level_changed_callback(handle, Some(level_changed)) })
+}
+
+/// Forgets the callbacks of a context, when it is unregistered
+pub fn remove(handle: *mut DltContext) {
+    lock().retain(|&(registered, _, _)| registered != handle as usize);
+}
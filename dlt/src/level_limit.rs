@@ -0,0 +1,187 @@
+//! Application-wide log levels.
+//!
+//! Two knobs, both for all the contexts of the process:
+//!
+//! - the application limit of libdlt(`dlt_set_application_ll_ts_limit`), which sets the
+//!   level and trace status of every context, on the daemon side too
+//! - the ceiling, checked on the Rust side only, which no context may exceed whatever the
+//!   daemon says
+//!
+//! A `LevelGuard` raises the levels of the contexts and the ceiling for a bounded time, during
+//! a diagnostic session for example.
+
+use std::sync::{ Mutex, MutexGuard };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+
+use ffi::{ self, DltContext, DltTraceStatusType };
+use { check, file_mode, Level, Result };
+
+/// Raw level of the ceiling, `NO_LEVEL` for none
+static CEILING: AtomicUsize     = AtomicUsize::new(NO_LEVEL);
+/// Raw level of the last application limit, `NO_LEVEL` if none was set
+static LIMIT: AtomicUsize       = AtomicUsize::new(NO_LEVEL);
+static LIMIT_TRACE: AtomicBool  = AtomicBool::new(false);
+
+const NO_LEVEL: usize = 0;
+
+/// Handles(`*mut DltContext`) of the registered contexts, whose levels the guards raise
+static CONTEXTS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+fn contexts() -> MutexGuard<'static, Vec<usize>> {
+    match CONTEXTS.lock() {
+        Ok(contexts)  => contexts,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Tracks a registered context, until `remove_context`
+pub(crate) fn add_context(handle: *mut DltContext) {
+    contexts().push(handle as usize);
+}
+
+/// Stops tracking a context, before it is unregistered
+pub(crate) fn remove_context(handle: *mut DltContext) {
+    contexts().retain(|&context| context != handle as usize);
+}
+
+fn from_raw(raw: usize) -> Option<Level> {
+    Level::from_raw(raw as u8)
+}
+
+fn to_raw(level: Option<Level>) -> usize {
+    level.map_or(NO_LEVEL, |level| level as usize)
+}
+
+/// Sets the level and trace status of all the contexts of the application
+pub fn set_application_limit(level: Level, trace: bool) -> Result<()> {
+    let trace_status = if trace {
+        DltTraceStatusType::DLT_TRACE_STATUS_ON
+    } else {
+        DltTraceStatusType::DLT_TRACE_STATUS_OFF
+    };
+    check(unsafe { ffi::dlt_set_application_ll_ts_limit(level.to_ffi(), trace_status) })?;
+
+    LIMIT.store(to_raw(Some(level)), Ordering::Relaxed);
+    LIMIT_TRACE.store(trace, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The last limit set with `set_application_limit`, and its trace status
+pub fn application_limit() -> Option<(Level, bool)> {
+    from_raw(LIMIT.load(Ordering::Relaxed)).map(|level| (level, LIMIT_TRACE.load(Ordering::Relaxed)))
+}
+
+/// Caps the level of every context, `None` removes the ceiling
+pub fn set_level_ceiling(ceiling: Option<Level>) {
+    CEILING.store(to_raw(ceiling), Ordering::Relaxed);
+
+    #[cfg(feature = "log")]
+    ::logger::sync_max_level();
+}
+
+/// The ceiling of the context levels, if any
+pub fn level_ceiling() -> Option<Level> {
+    from_raw(CEILING.load(Ordering::Relaxed))
+}
+
+/// Applies the ceiling to a level set by the daemon
+pub(crate) fn cap(level: Option<Level>) -> Option<Level> {
+    match (level, level_ceiling()) {
+        (Some(level), Some(ceiling)) => Some(level.min(ceiling)),
+        (level, _)                   => level
+    }
+}
+
+/// Raises the level of all the registered contexts until dropped.
+///
+/// The levels are raised in the process only. Through the daemon(an application limit) they
+/// would come back asynchronously, possibly after the guard is gone, and the daemon would keep
+/// them. Dropping the guard restores the previous ceiling and the level each context had,
+/// unless the daemon set another one in the meantime. Guards are meant to be dropped in the
+/// reverse order of their creation.
+#[derive(Debug)]
+#[must_use = "the level goes back down when the guard is dropped"]
+pub struct LevelGuard {
+    ceiling: Option<Level>,
+    /// Handle, previous raw level and raised raw level of every context
+    levels: Vec<(usize, i8, i8)>
+}
+
+impl LevelGuard {
+    /// Raises every context to at least `level`, and the ceiling to it if needed
+    pub fn raise(level: Level) -> Result<LevelGuard> {
+        let shared = file_mode::shared();
+        let levels = contexts().iter().filter_map(|&handle| unsafe {
+            let context = &*(handle as *const DltContext);
+            if context.log_level_ptr.is_null() {
+                return None;
+            }
+
+            let previous = *context.log_level_ptr;
+            let raised   = previous.max(level as i8);
+            *context.log_level_ptr = raised;
+            Some((handle, previous, raised))
+        }).collect();
+        drop(shared);
+        let guard = LevelGuard { ceiling: level_ceiling(), levels };
+
+        // Also updates `log::max_level` from the raised levels
+        set_level_ceiling(guard.ceiling.map(|ceiling| ceiling.max(level)));
+        Ok(guard)
+    }
+}
+
+impl Drop for LevelGuard {
+    fn drop(&mut self) {
+        // Contexts unregistered in the meantime aren't tracked anymore
+        let shared   = file_mode::shared();
+        let contexts = contexts();
+        for &(handle, previous, raised) in self.levels.iter().filter(|(handle, _, _)| contexts.contains(handle)) {
+            unsafe {
+                let context = &*(handle as *const DltContext);
+                if !context.log_level_ptr.is_null() && *context.log_level_ptr == raised {
+                    *context.log_level_ptr = previous;
+                }
+            }
+        }
+        drop(contexts);
+        drop(shared);
+
+        // Last, it updates `log::max_level` from the restored levels
+        set_level_ceiling(self.ceiling);
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn guard_restores_the_context_levels() {
+    use std::thread;
+    use std::time::Duration;
+    use protocol::UserMessage;
+    use test_util::{ self, MockDaemon };
+
+    let _app   = test_util::register_application();
+    let daemon = MockDaemon::global();
+    let net    = test_util::register_and_wait("NET", "Network");
+    let db     = test_util::register_and_wait("DB", "Database");
+
+    for (context, level) in &[(&net, Level::Debug), (&db, Level::Warn)] {
+        daemon.set_log_level(context.id(), *level).unwrap();
+        assert!(test_util::wait_until(|| context.level() == Some(*level)));
+    }
+
+    let guard = LevelGuard::raise(Level::Verbose).unwrap();
+    assert_eq!((net.level(), db.level()), (Some(Level::Verbose), Some(Level::Verbose)));
+
+    // What the daemon sets during the window stays
+    daemon.set_log_level("DB", Level::Error).unwrap();
+    assert!(test_util::wait_until(|| db.level() == Some(Level::Error)));
+
+    drop(guard);
+    assert_eq!((net.level(), db.level()), (Some(Level::Debug), Some(Level::Error)));
+
+    // Nothing was sent to the daemon, so no late reply overwrites the restored levels
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!((net.level(), db.level()), (Some(Level::Debug), Some(Level::Error)));
+    assert!(!daemon.messages().iter().any(|message| matches!(*message, UserMessage::AppLogLevel { .. })));
+}
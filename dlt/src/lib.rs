@@ -1,2 +1,612 @@
+//! Safe wrapper around the GENIVI DLT user library.
+//!
+//! An application registers itself once with [`init`](fn.init.html), keeping the returned
+//! guard alive until the end of `main`, and then logs through one or more
+//! [`Context`](struct.Context.html)s:
+//!
+//! ```no_run
+//! let _dlt = dlt::init("RLOG", "Rust logging").unwrap();
+//! let ctx  = dlt::Context::register("TEST", "Rusty test").unwrap();
+//!
+//! ctx.log(dlt::Level::Info, "Hello from Rust").unwrap();
+//! ```
+
 extern crate libc;
+#[cfg(not(feature = "native"))]
 extern crate dlt_sys as ffi;
+#[cfg(any(feature = "native", feature = "test-util"))]
+#[macro_use]
+extern crate lazy_static;
+
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "tracing")]
+extern crate tracing_core;
+#[cfg(feature = "tracing")]
+extern crate tracing_subscriber;
+
+#[cfg(not(any(feature = "native", feature = "dlt-sys")))]
+compile_error!("Either the \"dlt-sys\"(default) or the \"native\" feature must be enabled");
+
+use std::cell::UnsafeCell;
+use std::error;
+use std::ffi::{ CString, NulError };
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::{ Arc, Mutex, MutexGuard };
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+
+use libc::c_int;
+
+use ffi::{ DltContext, DltContextData, DltLogLevelType, DltReturnValue };
+
+#[cfg(feature = "native")]
+mod native;
+#[cfg(feature = "native")]
+use native as ffi;
+
+mod buffer;
+mod config;
+mod context_map;
+mod file_mode;
+mod initial_levels;
+mod level_changed;
+mod level_limit;
+#[cfg(feature = "log")]
+mod logger;
+mod mirror;
+mod named;
+mod panic_hook;
+pub mod protocol;
+mod session;
+#[cfg(all(feature = "shm", not(feature = "native")))]
+mod shm;
+mod shm_ring;
+mod shutdown;
+#[cfg(feature = "test-util")]
+#[macro_use]
+pub mod test_util;
+#[cfg(feature = "tracing")]
+mod tracing_layer;
+mod version;
+
+pub use buffer::{ buffer_stats, flush_buffer, overflow_policy, resend_buffer, set_overflow_policy,
+                  BufferStats, OverflowPolicy };
+pub use config::{ client_connected, effective_config, Config, LogMode };
+pub use context_map::ContextMap;
+pub use file_mode::{ init_to_file, init_to_file_with, logging_to_file, switch_to_daemon, FileOptions, SyncPolicy };
+pub use initial_levels::{ set_initial_levels, LevelConfig, INITIAL_LOG_LEVEL_VAR };
+pub use level_limit::{ application_limit, level_ceiling, set_application_limit, set_level_ceiling, LevelGuard };
+#[cfg(feature = "log")]
+pub use logger::Logger;
+pub use mirror::{ console_mirror_enabled, set_console_mirror, set_console_mirror_enabled, ConsoleMirror };
+pub use panic_hook::install_panic_hook;
+pub use session::{ marker, split_session_records, split_sessions, Session, SessionLog };
+#[cfg(all(feature = "shm", not(feature = "native")))]
+pub use shm::{ ShmClient, ShmMessages, ShmServer, DEFAULT_SHM_KEY, DEFAULT_SHM_SIZE };
+pub use shm_ring::{ ShmRing, ShmStats };
+pub use shutdown::{ flush, init, init_with_config, FlushGuard, FlushReport, DEFAULT_FLUSH_DEADLINE };
+#[cfg(feature = "tracing")]
+pub use tracing_layer::DltLayer;
+pub use version::{ version, Version };
+
+/// Size of the application and context IDs, in bytes
+pub const ID_SIZE: usize = 4;
+
+/// `DLT_USER_TRACE_STATUS_NOT_SET`, keeps the initial trace status
+const TRACE_STATUS_NOT_SET: c_int = -2;
+
+/// Severity of a log message, maps one to one to `DltLogLevelType`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Fatal system error
+    Fatal   = 1,
+    /// Error with impact to correct functionality
+    Error   = 2,
+    /// Warning, correct behaviour could not be ensured
+    Warn    = 3,
+    /// Informational
+    Info    = 4,
+    /// Debug
+    Debug   = 5,
+    /// Highest grade of information
+    Verbose = 6
+}
+
+impl Level {
+    /// Converts the raw DLT log level(as found in the message info of the extended header)
+    pub fn from_raw(raw: u8) -> Option<Level> {
+        match raw {
+            1 => Some(Level::Fatal),
+            2 => Some(Level::Error),
+            3 => Some(Level::Warn),
+            4 => Some(Level::Info),
+            5 => Some(Level::Debug),
+            6 => Some(Level::Verbose),
+            _ => None
+        }
+    }
+
+    fn to_ffi(self) -> DltLogLevelType {
+        match self {
+            Level::Fatal   => DltLogLevelType::DLT_LOG_FATAL,
+            Level::Error   => DltLogLevelType::DLT_LOG_ERROR,
+            Level::Warn    => DltLogLevelType::DLT_LOG_WARN,
+            Level::Info    => DltLogLevelType::DLT_LOG_INFO,
+            Level::Debug   => DltLogLevelType::DLT_LOG_DEBUG,
+            Level::Verbose => DltLogLevelType::DLT_LOG_VERBOSE
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Level::Fatal   => "fatal",
+            Level::Error   => "error",
+            Level::Warn    => "warn",
+            Level::Info    => "info",
+            Level::Debug   => "debug",
+            Level::Verbose => "verbose"
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Errors reported by the safe DLT API
+#[derive(Debug)]
+pub enum Error {
+    /// Application and context IDs must have 1 to 4 ASCII characters
+    InvalidId(String),
+    /// A string handed to the C library contained an interior NUL byte
+    Nul(NulError),
+    /// The user library returned a negative `DltReturnValue`
+    Dlt(i32),
+    /// The operation isn't available with the user library in use
+    Unsupported(&'static str),
+    /// A configuration(initial log levels, ...) couldn't be parsed
+    InvalidConfig(String),
+    /// A file(log file, trace, ...) couldn't be read or written
+    Io(io::Error),
+    /// The linked libdlt isn't the version the bindings were built for
+    VersionMismatch { expected: String, linked: String }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidId(ref id)       => write!(f, "invalid DLT ID \"{}\": expected 1 to {} ASCII characters", id, ID_SIZE),
+            Error::Nul(ref err)            => write!(f, "{}", err),
+            Error::Unsupported(what)       => write!(f, "unsupported: {}", what),
+            Error::InvalidConfig(ref what) => write!(f, "invalid configuration: {}", what),
+            Error::Io(ref err)             => write!(f, "{}", err),
+            Error::VersionMismatch { ref expected, ref linked } => {
+                write!(f, "libdlt {} is linked, the bindings are for {}", linked, expected)
+            },
+            Error::Dlt(code)               => {
+                let reason = match code {
+                    -7 => "logging disabled",
+                    -6 => "user buffer full",
+                    -5 => "wrong parameter",
+                    -4 => "buffer full",
+                    -3 => "pipe full",
+                    -2 => "pipe error",
+                    _  => "error"
+                };
+                write!(f, "DLT returned {}({})", code, reason)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::InvalidId(_)     => "invalid DLT ID",
+            Error::Nul(_)           => "interior NUL byte",
+            Error::Dlt(_)           => "DLT user library error",
+            Error::Unsupported(_)   => "unsupported operation",
+            Error::InvalidConfig(_) => "invalid configuration",
+            Error::Io(_)            => "I/O error",
+            Error::VersionMismatch { .. } => "libdlt version mismatch"
+        }
+    }
+}
+
+impl From<NulError> for Error {
+    fn from(err: NulError) -> Error {
+        Error::Nul(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Turns a `DltReturnValue` into a `Result`, negative values being errors
+fn check(ret: DltReturnValue) -> Result<()> {
+    let code = ret as i32;
+    if code < 0 {
+        Err(Error::Dlt(code))
+    } else {
+        Ok(())
+    }
+}
+
+fn id_to_cstring(id: &str) -> Result<CString> {
+    if id.is_empty() || id.len() > ID_SIZE || !id.is_ascii() {
+        return Err(Error::InvalidId(id.to_owned()));
+    }
+
+    Ok(CString::new(id)?)
+}
+
+/// `duration` in milliseconds, the unit of the user library's timeouts
+fn duration_ms(duration: Duration) -> usize {
+    duration.as_secs() as usize * 1000 + duration.subsec_millis() as usize
+}
+
+/// Converts a 4 byte DLT ID, that may be padded with NUL bytes, to a `String`
+pub fn id_to_string(id: &[u8]) -> String {
+    let len = id.iter().position(|&byte| byte == 0).unwrap_or(id.len());
+    String::from_utf8_lossy(&id[..len]).into_owned()
+}
+
+/// ID of the registered application, empty when none is
+static APP_ID: Mutex<String> = Mutex::new(String::new());
+
+fn app_id() -> MutexGuard<'static, String> {
+    match APP_ID.lock() {
+        Ok(app_id)    => app_id,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Description of the registered application, to register it again with libdlt
+static APP_DESCRIPTION: Mutex<String> = Mutex::new(String::new());
+
+fn app_description() -> MutexGuard<'static, String> {
+    match APP_DESCRIPTION.lock() {
+        Ok(description) => description,
+        Err(poisoned)   => poisoned.into_inner()
+    }
+}
+
+/// Registration of the application with the DLT daemon.
+///
+/// There can be only one registered application per process,
+/// dropping it unregisters the application.
+#[derive(Debug)]
+pub struct Application {
+    id: String
+}
+
+impl Application {
+    /// Registers the application `id`(1 to 4 ASCII characters)
+    pub fn register(id: &str, description: &str) -> Result<Application> {
+        let c_id          = id_to_cstring(id)?;
+        let c_description = CString::new(description)?;
+
+        version::check_library_version()?;
+        let _shared = file_mode::shared();
+        check(unsafe { ffi::dlt_register_app(c_id.as_ptr(), c_description.as_ptr()) })?;
+        *app_id() = id.to_owned();
+        *app_description() = description.to_owned();
+
+        Ok(Application { id: id.to_owned() })
+    }
+
+    /// The application ID
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for Application {
+    fn drop(&mut self) {
+        let _shared = file_mode::shared();
+        app_id().clear();
+        app_description().clear();
+        unsafe { ffi::dlt_unregister_app(); }
+    }
+}
+
+/// A registered DLT context, the handle used for logging.
+///
+/// Clones share the registration, dropping the last one unregisters the context.
+#[derive(Clone)]
+pub struct Context {
+    inner: Arc<ContextInner>
+}
+
+struct ContextInner {
+    // The C library keeps a pointer to the `DltContext`, it must not move
+    handle: UnsafeCell<DltContext>,
+    id: String,
+    description: String,
+    /// `file_mode::generation` when the handle was registered, older ones were freed by libdlt
+    generation: AtomicUsize,
+    /// ID of the application registered at the time, for the console mirror
+    app_id: String
+}
+
+// The user library serializes the access to the context with its own semaphore
+unsafe impl Send for ContextInner {}
+unsafe impl Sync for ContextInner {}
+
+impl Context {
+    /// Registers the context `id`(1 to 4 ASCII characters) for the registered application.
+    ///
+    /// The initial log level comes from the `LevelConfig` set with `set_initial_levels`, or
+    /// from `DLT_INITIAL_LOG_LEVEL`, when one of their rules matches.
+    pub fn register(id: &str, description: &str) -> Result<Context> {
+        let c_id          = id_to_cstring(id)?;
+        let c_description = CString::new(description)?;
+
+        let _shared = file_mode::shared();
+        let context = Context::empty(id, description, &app_id());
+        check(unsafe {
+            match initial_levels::initial_level(&context.inner.app_id, id) {
+                Some(level) => {
+                    let level = level.map_or(DltLogLevelType::DLT_LOG_OFF as c_int, |level| level.to_ffi() as c_int);
+                    ffi::dlt_register_context_ll_ts(context.as_ptr(), c_id.as_ptr(), c_description.as_ptr(),
+                                                    level, TRACE_STATUS_NOT_SET)
+                },
+                None => ffi::dlt_register_context(context.as_ptr(), c_id.as_ptr(), c_description.as_ptr())
+            }
+        })?;
+        level_limit::add_context(context.as_ptr());
+        file_mode::add_context(&context.inner);
+
+        Ok(context)
+    }
+
+    fn empty(id: &str, description: &str, app_id: &str) -> Context {
+        Context {
+            inner: Arc::new(ContextInner {
+                handle: UnsafeCell::new(DltContext {
+                    contextID: [0; ID_SIZE],
+                    log_level_pos: 0,
+                    log_level_ptr: ptr::null_mut(),
+                    trace_status_ptr: ptr::null_mut(),
+                    mcnt: 0
+                }),
+                id: id.to_owned(),
+                description: description.to_owned(),
+                generation: AtomicUsize::new(file_mode::generation()),
+                app_id: app_id.to_owned()
+            })
+        }
+    }
+
+    /// The context ID
+    pub fn id(&self) -> &str {
+        &self.inner.id
+    }
+
+    /// The description the context was registered with
+    pub fn description(&self) -> &str {
+        &self.inner.description
+    }
+
+    /// Raw handle, for calling into `dlt_sys` directly(or into the pure-Rust
+    /// implementation when the `native` feature is enabled)
+    pub fn as_ptr(&self) -> *mut DltContext {
+        self.inner.handle.get()
+    }
+
+    /// The log level set by the daemon for this context, capped by the level ceiling.
+    ///
+    /// ### Returns
+    /// `None` if logging is off
+    pub fn level(&self) -> Option<Level> {
+        let _shared = file_mode::shared();
+        let context = unsafe { &*self.as_ptr() };
+        if context.log_level_ptr.is_null() {
+            return None;
+        }
+
+        level_limit::cap(Level::from_raw(unsafe { *context.log_level_ptr } as u8))
+    }
+
+    /// Calls `callback` with the new level(`None` for off) each time the daemon changes it.
+    ///
+    /// The callbacks run on the thread of the user library, until the context is unregistered.
+    pub fn on_level_changed<F>(&self, callback: F) -> Result<()>
+        where F: Fn(Option<Level>) + Send + Sync + 'static
+    {
+        let _shared = file_mode::shared();
+        level_changed::register(self, Arc::new(callback))
+    }
+
+    /// Checks the log level set by the daemon for this context, and the level ceiling
+    pub fn is_enabled(&self, level: Level) -> bool {
+        if level_ceiling().is_some_and(|ceiling| level > ceiling) {
+            return false;
+        }
+
+        let _shared = file_mode::shared();
+        unsafe {
+            ffi::dlt_user_is_logLevel_enabled(self.as_ptr(), level.to_ffi()) == DltReturnValue::DLT_RETURN_TRUE
+        }
+    }
+
+    /// Starts a verbose message with the given level.
+    ///
+    /// ### Returns
+    /// `None` if the level is disabled for this context
+    pub fn message(&self, level: Level) -> Option<Message<'_>> {
+        // Kept by the message, its data points into the handle
+        let shared = file_mode::shared();
+        if !self.is_enabled(level) {
+            return None;
+        }
+
+        let mut data: Box<DltContextData> = Box::new(unsafe { mem::zeroed() });
+        let ret = unsafe { ffi::dlt_user_log_write_start(self.as_ptr(), &mut *data, level.to_ffi()) };
+        if ret as i32 > 0 {
+            Some(Message { data, error: None, context: self, _shared: shared })
+        } else {
+            None
+        }
+    }
+
+    /// Logs `text` as a single string argument
+    pub fn log(&self, level: Level, text: &str) -> Result<()> {
+        match self.message(level) {
+            Some(mut message) => message.string(text).send(),
+            None              => Ok(())
+        }
+    }
+}
+
+impl Drop for ContextInner {
+    fn drop(&mut self) {
+        let _shared = file_mode::shared();
+        level_changed::remove(self.handle.get());
+        level_limit::remove_context(self.handle.get());
+        file_mode::remove_context(self);
+        if *self.generation.get_mut() == file_mode::generation() {
+            unsafe { ffi::dlt_unregister_context(self.handle.get()); }
+        }
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Context").field("id", &self.inner.id).finish()
+    }
+}
+
+/// A verbose message being built, created by `Context::message`.
+///
+/// Arguments are appended in order, the first failure is kept and reported by `send`.
+pub struct Message<'a> {
+    data: Box<DltContextData>,
+    error: Option<Error>,
+    context: &'a Context,
+    _shared: file_mode::Shared
+}
+
+macro_rules! write_arg {
+    ($(#[$attr:meta])* $name:ident, $ty:ty, $ffi:ident) => {
+        $(#[$attr])*
+        pub fn $name(&mut self, value: $ty) -> &mut Self {
+            let ret = unsafe { ffi::$ffi(&mut *self.data, value) };
+            self.record(ret)
+        }
+    };
+}
+
+macro_rules! write_named {
+    ($name:ident, $ty:ty, $type_info:expr) => {
+        /// Appends a named argument, see `named_string`
+        pub fn $name(&mut self, name: &str, value: $ty) -> &mut Self {
+            let ret = named::write_number(&mut self.data, $type_info, name, &value.to_ne_bytes());
+            self.record(ret)
+        }
+    };
+}
+
+impl<'a> Message<'a> {
+    fn record(&mut self, ret: DltReturnValue) -> &mut Self {
+        if self.error.is_none() {
+            if let Err(err) = check(ret) {
+                self.error = Some(err);
+            }
+        }
+
+        self
+    }
+
+    /// Appends a boolean argument
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        let ret = unsafe { ffi::dlt_user_log_write_bool(&mut *self.data, value as u8) };
+        self.record(ret)
+    }
+
+    write_arg!(u8,  u8,  dlt_user_log_write_uint8);
+    write_arg!(u16, u16, dlt_user_log_write_uint16);
+    write_arg!(u32, u32, dlt_user_log_write_uint32);
+    write_arg!(u64, u64, dlt_user_log_write_uint64);
+    write_arg!(i8,  i8,  dlt_user_log_write_int8);
+    write_arg!(i16, i16, dlt_user_log_write_int16);
+    write_arg!(i32, i32, dlt_user_log_write_int32);
+    write_arg!(i64, i64, dlt_user_log_write_int64);
+    write_arg!(f32, f32, dlt_user_log_write_float32);
+    write_arg!(f64, f64, dlt_user_log_write_float64);
+
+    /// Appends an UTF-8 string argument, NUL bytes are dropped
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        let value = match CString::new(value) {
+            Ok(value) => value,
+            Err(_)    => CString::new(value.replace('\0', "")).unwrap()
+        };
+
+        let ret = unsafe { ffi::dlt_user_log_write_utf8_string(&mut *self.data, value.as_ptr()) };
+        self.record(ret)
+    }
+
+    /// Appends a raw data argument, at most `u16::MAX` bytes
+    pub fn raw(&mut self, value: &[u8]) -> &mut Self {
+        if value.len() > u16::MAX as usize {
+            return self.record(DltReturnValue::DLT_RETURN_WRONG_PARAMETER);
+        }
+
+        let ret = unsafe {
+            ffi::dlt_user_log_write_raw(&mut *self.data, value.as_ptr() as *mut libc::c_void, value.len() as u16)
+        };
+        self.record(ret)
+    }
+
+    /// Appends a named boolean argument, see `named_string`
+    pub fn named_bool(&mut self, name: &str, value: bool) -> &mut Self {
+        let ret = named::write_bool(&mut self.data, name, value);
+        self.record(ret)
+    }
+
+    write_named!(named_u8,  u8,  protocol::TYPE_INFO_UINT | 1);
+    write_named!(named_u16, u16, protocol::TYPE_INFO_UINT | 2);
+    write_named!(named_u32, u32, protocol::TYPE_INFO_UINT | 3);
+    write_named!(named_u64, u64, protocol::TYPE_INFO_UINT | 4);
+    write_named!(named_i8,  i8,  protocol::TYPE_INFO_SINT | 1);
+    write_named!(named_i16, i16, protocol::TYPE_INFO_SINT | 2);
+    write_named!(named_i32, i32, protocol::TYPE_INFO_SINT | 3);
+    write_named!(named_i64, i64, protocol::TYPE_INFO_SINT | 4);
+    write_named!(named_f32, f32, protocol::TYPE_INFO_FLOA | 3);
+    write_named!(named_f64, f64, protocol::TYPE_INFO_FLOA | 4);
+
+    /// Appends a named UTF-8 string argument, shown as `name` by the DLT viewers.
+    ///
+    /// Named arguments always carry their type info, they are meant for verbose mode.
+    pub fn named_string(&mut self, name: &str, value: &str) -> &mut Self {
+        let ret = named::write_string(&mut self.data, name, value);
+        self.record(ret)
+    }
+
+    /// Sends the message to the daemon, applying the `OverflowPolicy` when the buffer is full
+    pub fn send(&mut self) -> Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        if mirror::console_mirror_enabled() {
+            mirror::mirror(&self.context.inner.app_id, self.context.id(), Level::from_raw(self.data.log_level as u8),
+                           &self.data.buffer[..self.data.size as usize], self.data.args_num as usize);
+        }
+
+        let size = self.data.size as usize;
+        let data = &mut *self.data;
+        file_mode::write(size, || buffer::send(|| unsafe { ffi::dlt_user_log_write_finish(data) }))
+    }
+}
@@ -0,0 +1,179 @@
+//! `log` integration, enabled with the `log` feature.
+//!
+//! The message is the first argument, the key/values of the record follow as named
+//! arguments.
+//!
+//! `log::max_level` follows the most verbose level the daemon enabled for the registered
+//! contexts, disabled records are dropped before being formatted. A target is registered
+//! by its first record passing that filter, `Info` until any context is registered.
+//!
+//! ```no_run
+//! # #[macro_use] extern crate log;
+//! # extern crate dlt;
+//! # fn main() {
+//! let _dlt = dlt::init("RLOG", "Rust logging").unwrap();
+//! dlt::Logger::new().install().unwrap();
+//!
+//! info!("Hello from Rust");
+//! # }
+//! ```
+
+use log::kv::{ self, Key, Value, VisitSource };
+use log::{ self, LevelFilter, Log, Metadata, Record, SetLoggerError };
+use std::sync::OnceLock;
+
+use buffer::flush_buffer;
+use context_map::ContextMap;
+use shutdown::DEFAULT_FLUSH_DEADLINE;
+use { Level, Message };
+
+static INSTALLED: OnceLock<&'static Logger> = OnceLock::new();
+
+/// `log::Log` implementation sending the records to the contexts of their targets
+pub struct Logger {
+    contexts: ContextMap
+}
+
+impl Logger {
+    /// Logger giving every target a hashed context ID
+    pub fn new() -> Logger {
+        Logger::with_context_map(ContextMap::new())
+    }
+
+    /// Logger resolving the targets with `contexts`
+    pub fn with_context_map(contexts: ContextMap) -> Logger {
+        Logger { contexts }
+    }
+
+    /// The mapping of targets to contexts
+    pub fn context_map(&self) -> &ContextMap {
+        &self.contexts
+    }
+
+    /// Installs the logger for the `log` macros, the levels are filtered by the daemon
+    pub fn install(mut self) -> Result<(), SetLoggerError> {
+        self.contexts.set_on_register(Box::new(|context| {
+            let _ = context.on_level_changed(|_| sync_max_level());
+            sync_max_level();
+        }));
+
+        let logger: &'static Logger = Box::leak(Box::new(self));
+        log::set_logger(logger)?;
+        let _ = INSTALLED.set(logger);
+        sync_max_level();
+        Ok(())
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Logger {
+        Logger::new()
+    }
+}
+
+fn to_level(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::Error,
+        log::Level::Warn  => Level::Warn,
+        log::Level::Info  => Level::Info,
+        log::Level::Debug => Level::Debug,
+        log::Level::Trace => Level::Verbose
+    }
+}
+
+fn to_filter(level: Option<Level>) -> LevelFilter {
+    match level {
+        Some(Level::Fatal) | Some(Level::Error) => LevelFilter::Error,
+        Some(Level::Warn)    => LevelFilter::Warn,
+        Some(Level::Info)    => LevelFilter::Info,
+        Some(Level::Debug)   => LevelFilter::Debug,
+        Some(Level::Verbose) => LevelFilter::Trace,
+        None                 => LevelFilter::Off
+    }
+}
+
+/// Sets `log::max_level` from the levels of the contexts of the installed logger
+pub(crate) fn sync_max_level() {
+    if let Some(logger) = INSTALLED.get() {
+        let levels = logger.contexts.levels();
+        let filter = if levels.is_empty() {
+            to_filter(::level_limit::cap(Some(Level::Info)))
+        } else {
+            levels.into_iter().map(|(_, level)| to_filter(level)).max().unwrap_or(LevelFilter::Off)
+        };
+
+        log::set_max_level(filter);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.contexts.context(metadata.target()) {
+            Ok(context) => context.is_enabled(to_level(metadata.level())),
+            Err(_)      => false
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let context = match self.contexts.context(record.target()) {
+            Ok(context) => context,
+            Err(_)      => return
+        };
+
+        if let Some(mut message) = context.message(to_level(record.level())) {
+            match record.args().as_str() {
+                Some(text) => message.string(text),
+                None       => message.string(&record.args().to_string())
+            };
+            let _ = record.key_values().visit(&mut KeyValueWriter { message: &mut message });
+            let _ = message.send();
+        }
+    }
+
+    fn flush(&self) {
+        let _ = flush_buffer(DEFAULT_FLUSH_DEADLINE);
+    }
+}
+
+/// Appends key/values as named arguments, keeping the type of the primitive values
+struct KeyValueWriter<'m, 'a: 'm> {
+    message: &'m mut Message<'a>
+}
+
+impl<'m, 'a, 'kvs> VisitSource<'kvs> for KeyValueWriter<'m, 'a> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let name = key.as_str();
+        if let Some(value) = value.to_bool() {
+            self.message.named_bool(name, value);
+        } else if let Some(value) = value.to_u64() {
+            self.message.named_u64(name, value);
+        } else if let Some(value) = value.to_i64() {
+            self.message.named_i64(name, value);
+        } else if let Some(value) = value.to_f64() {
+            self.message.named_f64(name, value);
+        } else if let Some(value) = value.to_borrowed_str() {
+            self.message.named_string(name, value);
+        } else {
+            self.message.named_string(name, &value.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn max_level_follows_the_daemon() {
+    use test_util::{ self, MockDaemon, DEFAULT_TIMEOUT };
+
+    let _app   = test_util::register_application();
+    let daemon = MockDaemon::global();
+    Logger::with_context_map(ContextMap::new().target("logger", "LOGR", "log records").unwrap()).install().unwrap();
+
+    log::info!(target: "logger", "registers the context");
+    assert!(daemon.wait_for_context("LOGR", DEFAULT_TIMEOUT));
+    assert_eq!(log::max_level(), LevelFilter::Info);
+
+    daemon.set_log_level("LOGR", Level::Debug).unwrap();
+    assert!(test_util::wait_until(|| log::max_level() == LevelFilter::Debug));
+}
@@ -0,0 +1,202 @@
+//! Console mirror: every message sent is also written, readably, to a terminal or any
+//! `io::Write`.
+//!
+//! Meant for running services on a workstation without a daemon, unlike libdlt's local
+//! print it doesn't depend on the daemon's state and it can be switched on and off at
+//! runtime:
+//!
+//! ```no_run
+//! let _dlt = dlt::init("RLOG", "Rust logging").unwrap();
+//! dlt::set_console_mirror(Some(dlt::ConsoleMirror::stderr()));
+//! ```
+//!
+//! gives lines like `12:04:31.207 RLOG TEST info Hello from Rust port=8080`.
+
+use std::io::{ self, IsTerminal, Write };
+use std::sync::{ Mutex, MutexGuard };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use protocol::{ self, Argument };
+use Level;
+
+/// Formats the messages for a writer
+pub struct ConsoleMirror {
+    writer: Box<dyn Write + Send>,
+    colors: bool,
+    timestamp: bool,
+    ids: bool
+}
+
+impl ConsoleMirror {
+    /// Mirror writing to `writer`, without colors
+    pub fn new<W: Write + Send + 'static>(writer: W) -> ConsoleMirror {
+        ConsoleMirror { writer: Box::new(writer), colors: false, timestamp: true, ids: true }
+    }
+
+    /// Mirror writing to stdout, with colors when it is a terminal
+    pub fn stdout() -> ConsoleMirror {
+        let colors = io::stdout().is_terminal();
+        ConsoleMirror::new(io::stdout()).with_colors(colors)
+    }
+
+    /// Mirror writing to stderr, with colors when it is a terminal
+    pub fn stderr() -> ConsoleMirror {
+        let colors = io::stderr().is_terminal();
+        ConsoleMirror::new(io::stderr()).with_colors(colors)
+    }
+
+    /// Colors the level with ANSI escape sequences
+    pub fn with_colors(mut self, colors: bool) -> ConsoleMirror {
+        self.colors = colors;
+        self
+    }
+
+    /// Starts the lines with the UTC time of day
+    pub fn with_timestamp(mut self, timestamp: bool) -> ConsoleMirror {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Shows the application and context IDs
+    pub fn with_ids(mut self, ids: bool) -> ConsoleMirror {
+        self.ids = ids;
+        self
+    }
+
+    fn write(&mut self, app_id: &str, context_id: &str, level: Option<Level>, text: &str) -> io::Result<()> {
+        let mut line = String::with_capacity(64 + text.len());
+        if self.timestamp {
+            line.push_str(&time_of_day(SystemTime::now()));
+            line.push(' ');
+        }
+        if self.ids {
+            line.push_str(app_id);
+            line.push(' ');
+            line.push_str(context_id);
+            line.push(' ');
+        }
+
+        let level_name = level.map_or("off".to_owned(), |level| level.to_string());
+        match level.filter(|_| self.colors) {
+            Some(level) => line.push_str(&format!("\x1b[{}m{:<7}\x1b[0m", color(level), level_name)),
+            None        => line.push_str(&format!("{:<7}", level_name))
+        }
+        line.push(' ');
+        line.push_str(text);
+        line.push('\n');
+
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+fn color(level: Level) -> &'static str {
+    match level {
+        Level::Fatal   => "1;31",
+        Level::Error   => "31",
+        Level::Warn    => "33",
+        Level::Info    => "32",
+        Level::Debug   => "36",
+        Level::Verbose => "90"
+    }
+}
+
+/// `HH:MM:SS.mmm` in UTC
+fn time_of_day(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds     = since_epoch.as_secs() % 86_400;
+    format!("{:02}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, since_epoch.subsec_millis())
+}
+
+/// Arguments joined by spaces, the named ones as `name=value`
+fn text(arguments: &[Argument]) -> String {
+    let mut text = String::new();
+    for argument in arguments {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        if let Some(ref name) = argument.name {
+            text.push_str(name);
+            text.push('=');
+        }
+        text.push_str(&argument.value.to_string());
+    }
+
+    text
+}
+
+/// Checked for every message before taking the lock
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static MIRROR: Mutex<Option<ConsoleMirror>> = Mutex::new(None);
+
+fn lock() -> MutexGuard<'static, Option<ConsoleMirror>> {
+    match MIRROR.lock() {
+        Ok(mirror)    => mirror,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Mirrors the messages of all the contexts to `mirror`, `None` stops mirroring
+pub fn set_console_mirror(mirror: Option<ConsoleMirror>) {
+    let mut current = lock();
+    ENABLED.store(mirror.is_some(), Ordering::Relaxed);
+    *current = mirror;
+}
+
+/// Pauses or resumes the mirror set with `set_console_mirror`
+pub fn set_console_mirror_enabled(enabled: bool) {
+    let current = lock();
+    ENABLED.store(enabled && current.is_some(), Ordering::Relaxed);
+}
+
+/// Whether messages are being mirrored
+pub fn console_mirror_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Writes a message about to be sent, `payload` holds its verbose arguments in host order
+pub(crate) fn mirror(app_id: &str, context_id: &str, level: Option<Level>, payload: &[u8], args_num: usize) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // Verbose payloads are made of the `args_num` self-describing arguments only
+    let text = match protocol::parse_all_arguments(payload, cfg!(target_endian = "big")) {
+        Some(ref arguments) if arguments.len() == args_num => text(arguments),
+        _ => protocol::Value::Raw(payload.to_vec()).to_string()
+    };
+
+    if let Some(ref mut mirror) = *lock() {
+        // A console that went away must not break logging
+        let _ = mirror.write(app_id, context_id, level, &text);
+    }
+}
+
+#[test]
+fn mirrored_line_format() {
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let output      = Shared(Arc::new(Mutex::new(Vec::new())));
+    let mut plain   = ConsoleMirror::new(output.clone()).with_timestamp(false);
+    let mut colored = ConsoleMirror::new(output.clone()).with_timestamp(false).with_colors(true);
+    plain.write("RLOG", "TEST", Some(Level::Warn), "disk almost full used=97").unwrap();
+    colored.write("RLOG", "TEST", Some(Level::Error), "disk full").unwrap();
+
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(output, "RLOG TEST warn    disk almost full used=97\nRLOG TEST \x1b[31merror  \x1b[0m disk full\n");
+    assert_eq!(time_of_day(UNIX_EPOCH + ::std::time::Duration::from_millis(3_723_004)), "01:02:03.004");
+}
@@ -0,0 +1,88 @@
+//! Named(VARI) verbose arguments.
+//!
+//! The user library only writes anonymous arguments, the named ones are encoded here directly
+//! into the payload of the `DltContextData`. They always carry their type info, which makes
+//! them verbose only.
+
+use ffi::{ self, DltContextData, DltReturnValue };
+use protocol::{ TYPE_INFO_BOOL, TYPE_INFO_SCOD_UTF8, TYPE_INFO_STRG, TYPE_INFO_VARI };
+
+/// Name, or unit, as written in the payload: NUL terminated, without inner NUL bytes
+fn terminated(text: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = text.bytes().filter(|&byte| byte != 0).collect();
+    bytes.push(0);
+    bytes
+}
+
+fn append(data: &mut DltContextData, parts: &[&[u8]]) -> DltReturnValue {
+    let size   = data.size as usize;
+    let needed = parts.iter().map(|part| part.len()).sum::<usize>();
+    if size + needed > ffi::DLT_USER_BUF_MAX_SIZE {
+        return DltReturnValue::DLT_RETURN_USER_BUFFER_FULL;
+    }
+
+    let mut pos = size;
+    for part in parts {
+        data.buffer[pos..pos + part.len()].copy_from_slice(part);
+        pos += part.len();
+    }
+
+    data.size      = pos as i32;
+    data.args_num += 1;
+
+    DltReturnValue::DLT_RETURN_OK
+}
+
+/// Appends a named number, `type_info` gives its type and length, `value` is in host order.
+///
+/// The unit is left empty.
+pub fn write_number(data: &mut DltContextData, type_info: u32, name: &str, value: &[u8]) -> DltReturnValue {
+    let name = terminated(name);
+    append(data, &[&(type_info | TYPE_INFO_VARI).to_ne_bytes(),
+                   &(name.len() as u16).to_ne_bytes(),
+                   &0u16.to_ne_bytes(),
+                   &name,
+                   value])
+}
+
+/// Appends a named boolean, which has no unit
+pub fn write_bool(data: &mut DltContextData, name: &str, value: bool) -> DltReturnValue {
+    let name = terminated(name);
+    append(data, &[&(TYPE_INFO_BOOL | 1 | TYPE_INFO_VARI).to_ne_bytes(),
+                   &(name.len() as u16).to_ne_bytes(),
+                   &name,
+                   &[value as u8]])
+}
+
+/// Appends a named UTF-8 string
+pub fn write_string(data: &mut DltContextData, name: &str, value: &str) -> DltReturnValue {
+    let name  = terminated(name);
+    let value = terminated(value);
+    if value.len() > u16::MAX as usize {
+        return DltReturnValue::DLT_RETURN_USER_BUFFER_FULL;
+    }
+
+    append(data, &[&(TYPE_INFO_STRG | TYPE_INFO_SCOD_UTF8 | TYPE_INFO_VARI).to_ne_bytes(),
+                   &(value.len() as u16).to_ne_bytes(),
+                   &(name.len() as u16).to_ne_bytes(),
+                   &name,
+                   &value])
+}
+
+#[test]
+fn named_arguments_round_trip() {
+    use protocol::{ parse_arguments, Argument, Value, TYPE_INFO_UINT };
+
+    let mut data: DltContextData = unsafe { ::std::mem::zeroed() };
+    write_number(&mut data, TYPE_INFO_UINT | 3, "port", &8080u32.to_ne_bytes());
+    write_bool(&mut data, "secure", true);
+    write_string(&mut data, "host", "localhost");
+
+    let arguments = parse_arguments(&data.buffer[..data.size as usize], cfg!(target_endian = "big"));
+    assert_eq!(data.args_num, 3);
+    assert_eq!(arguments, vec![
+        Argument { name: Some("port".to_owned()), value: Value::Unsigned(8080) },
+        Argument { name: Some("secure".to_owned()), value: Value::Bool(true) },
+        Argument { name: Some("host".to_owned()), value: Value::String("localhost".to_owned()) }
+    ]);
+}
@@ -0,0 +1,127 @@
+//! Log file written instead of the daemon FIFO, what `dlt_init_file` does in libdlt.
+//!
+//! Every message is preceded by a storage header and written with a single call, so the
+//! file only ever holds complete messages. On top of libdlt, the file can be rotated when it
+//! reaches a size and synced to disk.
+
+use std::fs::{ self, File };
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant, SystemTime };
+
+use file_mode::rotated;
+use protocol;
+
+/// Size, rotation and sync of the log file, what `dlt::FileOptions` configures
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct SinkOptions {
+    /// Size the file is rotated at, `0` for no limit
+    pub max_size: u64,
+    /// Rotated files kept next to the current one(`trace.1.dlt`, `trace.2.dlt`, ...)
+    pub max_files: u32,
+    /// `None` never syncs, `Some(0)` after every message
+    pub sync_interval: Option<Duration>
+}
+
+pub struct FileSink {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    options: SinkOptions,
+    last_sync: Instant
+}
+
+impl FileSink {
+    /// Creates, or truncates, the file at `path`
+    pub fn create(path: &Path, options: SinkOptions) -> io::Result<FileSink> {
+        Ok(FileSink {
+            path: path.to_owned(),
+            file: File::create(path)?,
+            size: 0,
+            options,
+            last_sync: Instant::now()
+        })
+    }
+
+    pub fn set_options(&mut self, options: SinkOptions) {
+        self.options = options;
+    }
+
+    /// Writes a DLT message(starting with the standard header) with its storage header
+    pub fn write(&mut self, ecu_id: &[u8], message: &[u8]) -> io::Result<()> {
+        let len = (protocol::STORAGE_HEADER_SIZE + message.len()) as u64;
+        if self.options.max_size > 0 && self.size > 0 && self.size + len > self.options.max_size {
+            self.rotate()?;
+        }
+
+        let mut stored = Vec::with_capacity(len as usize);
+        stored.extend_from_slice(&protocol::storage_header(SystemTime::now(), ecu_id));
+        stored.extend_from_slice(message);
+        self.file.write_all(&stored)?;
+        self.size += len;
+
+        match self.options.sync_interval {
+            Some(interval) if self.last_sync.elapsed() >= interval => self.sync(),
+            _ => Ok(())
+        }
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.last_sync = Instant::now();
+        self.file.sync_data()
+    }
+
+    /// Shifts the rotated files, the oldest one is removed
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+
+        let max_files = self.options.max_files;
+        if max_files > 0 {
+            let _ = fs::remove_file(rotated(&self.path, max_files));
+            for index in (1..max_files).rev() {
+                let _ = fs::rename(rotated(&self.path, index), rotated(&self.path, index + 1));
+            }
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        }
+
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        let _ = self.file.sync_data();
+    }
+}
+
+#[test]
+fn rotated_files_hold_complete_messages() {
+    use protocol::{ parse_stored_record, Parsed };
+
+    let dir  = ::std::env::temp_dir().join(format!("dlt-file-{}", ::std::process::id()));
+    let path = dir.join("trace.dlt");
+    fs::create_dir_all(&dir).unwrap();
+
+    // Standard header only: version 1 with ECU ID, counter, length 8
+    let message = [protocol::HTYP_VERSION1 | protocol::HTYP_WEID, 0, 0, 8, b'E', b'C', b'U', b'1'];
+    let options = SinkOptions { max_size: 2 * 24 + 10, max_files: 1, sync_interval: Some(Duration::from_secs(0)) };
+    let mut sink = FileSink::create(&path, options).unwrap();
+    for _ in 0..5 {
+        sink.write(b"ECU1", &message).unwrap();
+    }
+    drop(sink);
+
+    let current = fs::read(&path).unwrap();
+    assert_eq!(current.len(), 24);
+    assert_eq!(fs::read(rotated(&path, 1)).unwrap().len(), 48);
+    assert!(!rotated(&path, 2).exists());
+    match parse_stored_record(&current) {
+        Parsed::Complete(stored, 24) => assert_eq!(stored.ecu_id, "ECU1"),
+        other                        => panic!("unexpected parse result: {:?}", other)
+    }
+    assert_eq!(rotated(Path::new("trace"), 1), PathBuf::from("trace.1"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,499 @@
+//! Pure-Rust implementation of the application side of the DLT user library.
+//!
+//! It mirrors the subset of the `dlt_sys` API used by this crate(same names, types and
+//! return values), so the safe wrappers compile unchanged against either implementation.
+//! The application talks to the daemon like libdlt does: control and log messages are
+//! written to the daemon FIFO(`<dltFifoBaseDir>/dlt`), log level updates are read from the
+//! application FIFO(`<dltFifoBaseDir>/dltpipes/dlt<pid>`) and messages are kept in a ring
+//! buffer while the daemon isn't reachable.
+
+#![allow(non_camel_case_types, non_upper_case_globals, non_snake_case)]
+
+mod file;
+mod user;
+
+use std::ffi::{ CStr, OsStr };
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use libc::{ c_char, c_int, c_void, size_t, PATH_MAX };
+
+use protocol::{ TYPE_INFO_BOOL, TYPE_INFO_FLOA, TYPE_INFO_RAWD, TYPE_INFO_SCOD_UTF8, TYPE_INFO_SINT,
+                TYPE_INFO_STRG, TYPE_INFO_UINT };
+
+pub(crate) use self::file::SinkOptions;
+use self::user::{ lock, set_verbose_mode, verbose_mode };
+
+/// The size of a DLT ID
+pub const DLT_ID_SIZE: usize = 4;
+/// Maximum size of each user buffer
+pub const DLT_USER_BUF_MAX_SIZE: usize = 1390;
+
+/// Version of libdlt the implementation follows(protocol, layouts and behaviour)
+pub const DLT_PACKAGE_VERSION: &str = "2.17.0";
+pub const DLT_PACKAGE_MAJOR_VERSION: &str = "2";
+pub const DLT_PACKAGE_MINOR_VERSION: &str = "17";
+
+/// The common base-path of the daemon FIFO and application FIFOs, `/tmp` when left empty
+pub static mut dltFifoBaseDir: [c_char; PATH_MAX as usize + 1] = [0; PATH_MAX as usize + 1];
+
+/// Definitions of DLT return values
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DltReturnValue {
+    DLT_RETURN_LOGGING_DISABLED = -7,
+    DLT_RETURN_USER_BUFFER_FULL = -6,
+    DLT_RETURN_WRONG_PARAMETER  = -5,
+    DLT_RETURN_BUFFER_FULL      = -4,
+    DLT_RETURN_PIPE_FULL        = -3,
+    DLT_RETURN_PIPE_ERROR       = -2,
+    DLT_RETURN_ERROR            = -1,
+    DLT_RETURN_OK               = 0,
+    DLT_RETURN_TRUE             = 1
+}
+
+/// Definitions of DLT log level
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DltLogLevelType {
+    DLT_LOG_DEFAULT = -1,
+    DLT_LOG_OFF     = 0,
+    DLT_LOG_FATAL   = 1,
+    DLT_LOG_ERROR   = 2,
+    DLT_LOG_WARN    = 3,
+    DLT_LOG_INFO    = 4,
+    DLT_LOG_DEBUG   = 5,
+    DLT_LOG_VERBOSE = 6,
+    DLT_LOG_MAX     = 7
+}
+
+/// Definitions of DLT trace status
+#[allow(dead_code)]
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DltTraceStatusType {
+    DLT_TRACE_STATUS_DEFAULT = -1,
+    DLT_TRACE_STATUS_OFF     = 0,
+    DLT_TRACE_STATUS_ON      = 1,
+    DLT_TRACE_STATUS_MAX     = 2
+}
+
+/// Definitions of the log modes of the daemon
+#[allow(dead_code)]
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DltUserLogMode {
+    DLT_USER_MODE_UNDEFINED = -1,
+    DLT_USER_MODE_OFF       = 0,
+    DLT_USER_MODE_EXTERNAL  = 1,
+    DLT_USER_MODE_INTERNAL  = 2,
+    DLT_USER_MODE_BOTH      = 3,
+    DLT_USER_MODE_MAX       = 4
+}
+
+/// Same layout as the `DltContext` of libdlt
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DltContext {
+    /// Context ID
+    pub contextID: [c_char; DLT_ID_SIZE],
+    /// Offset in user-application context field
+    pub log_level_pos: i32,
+    /// Pointer to the log level
+    pub log_level_ptr: *mut i8,
+    /// Pointer to the trace status
+    pub trace_status_ptr: *mut i8,
+    /// Message counter
+    pub mcnt: u8
+}
+
+/// Same layout as the `DltContextData` of libdlt
+#[repr(C)]
+pub struct DltContextData {
+    /// Pointer to DltContext
+    pub handle: *mut DltContext,
+    /// Buffer for building log message
+    pub buffer: [u8; DLT_USER_BUF_MAX_SIZE],
+    /// Payload size
+    pub size: i32,
+    /// Log level
+    pub log_level: i32,
+    /// Trace status
+    pub trace_status: i32,
+    /// Number of arguments for extended header
+    pub args_num: i32,
+    /// Description of context
+    pub context_description: *mut c_char
+}
+
+/// Used when a context is registered without explicit log level or trace status
+const DLT_USER_LOG_LEVEL_NOT_SET: c_int    = -2;
+const DLT_USER_TRACE_STATUS_NOT_SET: c_int = -2;
+
+unsafe fn c_str<'a>(text: *const c_char) -> Option<&'a [u8]> {
+    if text.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(text).to_bytes())
+    }
+}
+
+pub unsafe fn dlt_init_file(name: *const c_char) -> DltReturnValue {
+    match c_str(name) {
+        Some(name) if !name.is_empty() => lock().init_file(Path::new(OsStr::from_bytes(name))),
+        _ => DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    }
+}
+
+/// Copies `text` to `buf` like `snprintf`: truncated and always NUL-terminated
+unsafe fn print_to_buffer(text: &str, buf: *mut c_char, size: size_t) {
+    if buf.is_null() || size == 0 {
+        return;
+    }
+
+    let len = text.len().min(size - 1);
+    ptr::copy_nonoverlapping(text.as_ptr() as *const c_char, buf, len);
+    *buf.add(len) = 0;
+}
+
+pub unsafe fn dlt_get_version(buf: *mut c_char, size: size_t) {
+    let version = format!("DLT Package Version: {}, native Rust user library", DLT_PACKAGE_VERSION);
+    print_to_buffer(&version, buf, size);
+}
+
+pub unsafe fn dlt_get_major_version(buf: *mut c_char, size: size_t) {
+    print_to_buffer(DLT_PACKAGE_MAJOR_VERSION, buf, size);
+}
+
+pub unsafe fn dlt_get_minor_version(buf: *mut c_char, size: size_t) {
+    print_to_buffer(DLT_PACKAGE_MINOR_VERSION, buf, size);
+}
+
+pub unsafe fn dlt_user_check_library_version(user_major_version: *const c_char,
+                                             user_minor_version: *const c_char) -> DltReturnValue {
+    if user_major_version.is_null() || user_minor_version.is_null() {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    let major = CStr::from_ptr(user_major_version).to_bytes();
+    let minor = CStr::from_ptr(user_minor_version).to_bytes();
+    if major != DLT_PACKAGE_MAJOR_VERSION.as_bytes() || minor != DLT_PACKAGE_MINOR_VERSION.as_bytes() {
+        return DltReturnValue::DLT_RETURN_ERROR;
+    }
+
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_register_app(appid: *const c_char, description: *const c_char) -> DltReturnValue {
+    let appid = match c_str(appid) {
+        Some(appid) if !appid.is_empty() => appid,
+        _ => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    };
+
+    lock().register_app(appid, c_str(description).unwrap_or(b""))
+}
+
+pub unsafe fn dlt_unregister_app() -> DltReturnValue {
+    lock().unregister_app()
+}
+
+pub unsafe fn dlt_register_context(handle: *mut DltContext, contextid: *const c_char,
+                                   description: *const c_char) -> DltReturnValue {
+    dlt_register_context_ll_ts(handle, contextid, description,
+                               DLT_USER_LOG_LEVEL_NOT_SET, DLT_USER_TRACE_STATUS_NOT_SET)
+}
+
+pub unsafe fn dlt_register_context_ll_ts(handle: *mut DltContext, contextid: *const c_char, description: *const c_char,
+                                         loglevel: c_int, tracestatus: c_int) -> DltReturnValue {
+    let handle = match handle.as_mut() {
+        Some(handle) => handle,
+        None         => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    };
+    let contextid = match c_str(contextid) {
+        Some(contextid) if !contextid.is_empty() => contextid,
+        _ => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    };
+
+    if loglevel < DLT_USER_LOG_LEVEL_NOT_SET || loglevel >= DltLogLevelType::DLT_LOG_MAX as c_int
+        || tracestatus < DLT_USER_TRACE_STATUS_NOT_SET || tracestatus >= DltTraceStatusType::DLT_TRACE_STATUS_MAX as c_int
+    {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    lock().register_context(handle, contextid, c_str(description).unwrap_or(b""), loglevel as i8, tracestatus as i8)
+}
+
+pub unsafe fn dlt_unregister_context(handle: *mut DltContext) -> DltReturnValue {
+    match handle.as_mut() {
+        Some(handle) => lock().unregister_context(handle),
+        None         => DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    }
+}
+
+/// Called with the context ID, log level and trace status when the daemon changes them
+pub type LogLevelChangedCallback = unsafe extern "C" fn(context_id: *mut c_char, log_level: u8, trace_status: u8);
+
+pub unsafe fn dlt_register_log_level_changed_callback(handle: *mut DltContext,
+                                                      callback: Option<LogLevelChangedCallback>) -> DltReturnValue {
+    match handle.as_ref() {
+        Some(handle) => lock().set_log_level_changed_callback(handle, callback),
+        None         => DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    }
+}
+
+#[inline]
+pub unsafe fn dlt_user_is_logLevel_enabled(handle: *mut DltContext, loglevel: DltLogLevelType) -> DltReturnValue {
+    let handle = match handle.as_ref() {
+        Some(handle) if !handle.log_level_ptr.is_null() => handle,
+        _ => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    };
+
+    if loglevel as i8 <= *handle.log_level_ptr && loglevel != DltLogLevelType::DLT_LOG_OFF {
+        return DltReturnValue::DLT_RETURN_TRUE;
+    }
+
+    DltReturnValue::DLT_RETURN_LOGGING_DISABLED
+}
+
+pub unsafe fn dlt_user_log_write_start(handle: *mut DltContext, log: *mut DltContextData,
+                                       loglevel: DltLogLevelType) -> DltReturnValue {
+    let log = match log.as_mut() {
+        Some(log) if !handle.is_null() => log,
+        _ => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    };
+
+    if loglevel as i32 <= DltLogLevelType::DLT_LOG_DEFAULT as i32 || loglevel as i32 >= DltLogLevelType::DLT_LOG_MAX as i32 {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    if dlt_user_is_logLevel_enabled(handle, loglevel) != DltReturnValue::DLT_RETURN_TRUE {
+        return DltReturnValue::DLT_RETURN_OK;
+    }
+
+    log.handle       = handle;
+    log.log_level    = loglevel as i32;
+    log.trace_status = 0;
+    log.size         = 0;
+    log.args_num     = 0;
+
+    DltReturnValue::DLT_RETURN_TRUE
+}
+
+pub unsafe fn dlt_user_log_write_finish(log: *mut DltContextData) -> DltReturnValue {
+    match log.as_mut() {
+        Some(log) if !log.handle.is_null() => lock().send_log(log),
+        _ => DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    }
+}
+
+pub unsafe fn dlt_user_check_buffer(total_size: *mut c_int, used_size: *mut c_int) -> DltReturnValue {
+    if total_size.is_null() || used_size.is_null() {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    let (total, used) = lock().check_buffer();
+    *total_size = total as c_int;
+    *used_size  = used as c_int;
+
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_user_log_resend_buffer() -> DltReturnValue {
+    lock().resend_buffer()
+}
+
+pub unsafe fn dlt_set_resend_timeout_atexit(timeout_in_milliseconds: u32) -> c_int {
+    lock().set_resend_timeout_atexit(timeout_in_milliseconds);
+    0
+}
+
+pub unsafe fn dlt_set_application_ll_ts_limit(loglevel: DltLogLevelType, tracestatus: DltTraceStatusType) -> DltReturnValue {
+    if (loglevel as c_int) < DltLogLevelType::DLT_LOG_DEFAULT as c_int || loglevel as c_int >= DltLogLevelType::DLT_LOG_MAX as c_int
+        || (tracestatus as c_int) < DltTraceStatusType::DLT_TRACE_STATUS_DEFAULT as c_int
+        || tracestatus as c_int >= DltTraceStatusType::DLT_TRACE_STATUS_MAX as c_int
+    {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    lock().set_application_ll_ts_limit(loglevel as i8, tracestatus as i8)
+}
+
+pub unsafe fn dlt_set_log_mode(mode: DltUserLogMode) -> DltReturnValue {
+    if (mode as c_int) < DltUserLogMode::DLT_USER_MODE_OFF as c_int || mode as c_int >= DltUserLogMode::DLT_USER_MODE_MAX as c_int {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    lock().set_log_mode(mode as i8)
+}
+
+pub unsafe fn dlt_log_marker() -> DltReturnValue {
+    lock().log_marker()
+}
+
+pub unsafe fn dlt_get_log_state() -> c_int {
+    lock().log_state() as c_int
+}
+
+pub unsafe fn dlt_verbose_mode() -> DltReturnValue {
+    set_verbose_mode(true);
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_nonverbose_mode() -> DltReturnValue {
+    set_verbose_mode(false);
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_use_extended_header_for_non_verbose(use_extende_header_for_non_verbose: i8) -> DltReturnValue {
+    lock().header_options().use_extended_header_for_non_verbose = use_extende_header_for_non_verbose != 0;
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_with_session_id(with_session_id: i8) -> DltReturnValue {
+    lock().header_options().with_session_id = with_session_id != 0;
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_with_timestamp(with_timestamp: i8) -> DltReturnValue {
+    lock().header_options().with_timestamp = with_timestamp != 0;
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_with_ecu_id(with_ecu_id: i8) -> DltReturnValue {
+    lock().header_options().with_ecu_id = with_ecu_id != 0;
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_enable_local_print() -> DltReturnValue {
+    lock().set_local_print(true);
+    DltReturnValue::DLT_RETURN_OK
+}
+
+pub unsafe fn dlt_disable_local_print() -> DltReturnValue {
+    lock().set_local_print(false);
+    DltReturnValue::DLT_RETURN_OK
+}
+
+/// Not part of libdlt: size, rotation and sync of the file written after `dlt_init_file`
+pub(crate) fn dlt_user_set_file_options(options: SinkOptions) {
+    lock().set_file_options(options)
+}
+
+/// Not part of libdlt: closes the file of `dlt_init_file`, the messages go to the daemon again
+pub fn dlt_user_close_file() -> DltReturnValue {
+    lock().close_file()
+}
+
+/// Not part of libdlt: evict the oldest buffered messages when the startup buffer is full
+pub fn dlt_user_set_drop_oldest(enable: bool) {
+    lock().set_drop_oldest(enable)
+}
+
+/// Not part of libdlt: number of messages evicted from the startup buffer
+pub fn dlt_user_get_dropped_oldest() -> u32 {
+    lock().dropped_oldest()
+}
+
+/// Not part of libdlt: whether the current thread is inside the user library, logging from a
+/// panic hook would deadlock then
+pub fn dlt_user_is_locked_by_current_thread() -> bool {
+    user::locked_by_current_thread()
+}
+
+/// Not part of libdlt: number of messages in the startup buffer
+pub fn dlt_user_get_buffered_messages() -> usize {
+    lock().buffered_messages()
+}
+
+/// Appends one argument(type info followed by `parts`) to the payload being built
+unsafe fn write_argument(log: *mut DltContextData, type_info: u32, parts: &[&[u8]]) -> DltReturnValue {
+    let log = match log.as_mut() {
+        Some(log) => log,
+        None      => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+    };
+
+    let verbose   = verbose_mode();
+    let type_size = if verbose { 4 } else { 0 };
+    let size      = log.size as usize;
+    let needed    = type_size + parts.iter().map(|part| part.len()).sum::<usize>();
+    if size + needed > DLT_USER_BUF_MAX_SIZE {
+        return DltReturnValue::DLT_RETURN_USER_BUFFER_FULL;
+    }
+
+    let mut pos = size;
+    if verbose {
+        log.buffer[pos..pos + 4].copy_from_slice(&type_info.to_ne_bytes());
+        pos += 4;
+    }
+    for part in parts {
+        log.buffer[pos..pos + part.len()].copy_from_slice(part);
+        pos += part.len();
+    }
+
+    log.size      = pos as i32;
+    log.args_num += 1;
+
+    DltReturnValue::DLT_RETURN_OK
+}
+
+/// Type length field for 8, 16, 32 and 64 bit values
+fn tyle(size: usize) -> u32 {
+    match size {
+        1 => 1,
+        2 => 2,
+        4 => 3,
+        _ => 4
+    }
+}
+
+macro_rules! write_number {
+    ($name:ident, $ty:ty, $kind:expr) => {
+        pub unsafe fn $name(log: *mut DltContextData, data: $ty) -> DltReturnValue {
+            let bytes = data.to_ne_bytes();
+            write_argument(log, $kind | tyle(bytes.len()), &[&bytes])
+        }
+    };
+}
+
+write_number!(dlt_user_log_write_uint8,   u8,  TYPE_INFO_UINT);
+write_number!(dlt_user_log_write_uint16,  u16, TYPE_INFO_UINT);
+write_number!(dlt_user_log_write_uint32,  u32, TYPE_INFO_UINT);
+write_number!(dlt_user_log_write_uint64,  u64, TYPE_INFO_UINT);
+write_number!(dlt_user_log_write_int8,    i8,  TYPE_INFO_SINT);
+write_number!(dlt_user_log_write_int16,   i16, TYPE_INFO_SINT);
+write_number!(dlt_user_log_write_int32,   i32, TYPE_INFO_SINT);
+write_number!(dlt_user_log_write_int64,   i64, TYPE_INFO_SINT);
+write_number!(dlt_user_log_write_float32, f32, TYPE_INFO_FLOA);
+write_number!(dlt_user_log_write_float64, f64, TYPE_INFO_FLOA);
+
+pub unsafe fn dlt_user_log_write_bool(log: *mut DltContextData, data: u8) -> DltReturnValue {
+    write_argument(log, TYPE_INFO_BOOL | tyle(1), &[&[data]])
+}
+
+unsafe fn write_string(log: *mut DltContextData, text: *const c_char, encoding: u32) -> DltReturnValue {
+    if text.is_null() {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    let text = CStr::from_ptr(text).to_bytes_with_nul();
+    if text.len() > u16::MAX as usize {
+        return DltReturnValue::DLT_RETURN_USER_BUFFER_FULL;
+    }
+
+    write_argument(log, TYPE_INFO_STRG | encoding, &[&(text.len() as u16).to_ne_bytes(), text])
+}
+
+pub unsafe fn dlt_user_log_write_utf8_string(log: *mut DltContextData, text: *const c_char) -> DltReturnValue {
+    write_string(log, text, TYPE_INFO_SCOD_UTF8)
+}
+
+pub unsafe fn dlt_user_log_write_raw(log: *mut DltContextData, data: *mut c_void, length: u16) -> DltReturnValue {
+    if data.is_null() && length > 0 {
+        return DltReturnValue::DLT_RETURN_WRONG_PARAMETER;
+    }
+
+    let data: &[u8] = if length == 0 { &[] } else { slice::from_raw_parts(data as *const u8, length as usize) };
+    write_argument(log, TYPE_INFO_RAWD, &[&length.to_ne_bytes(), data])
+}
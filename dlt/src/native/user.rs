@@ -0,0 +1,901 @@
+//! Per-process state of the native user library: registrations, the FIFO connection
+//! to the daemon and the startup buffer.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::ffi::{ CStr, CString, OsStr };
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Read, Write };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{ OpenOptionsExt, PermissionsExt };
+use std::ops::{ Deref, DerefMut };
+use std::os::unix::io::AsRawFd;
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::ptr;
+use std::sync::{ Mutex, MutexGuard };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use libc::{ self, c_int };
+
+use protocol::{ self, HTYP_MSBF, HTYP_UEH, HTYP_VERSION1, HTYP_WEID, HTYP_WSID, HTYP_WTMS, MSIN_VERB, MSTP_LOG };
+use super::file::{ FileSink, SinkOptions };
+use super::{ dltFifoBaseDir, DltContext, DltContextData, DltReturnValue, LogLevelChangedCallback, DLT_ID_SIZE };
+
+/// Log level of newly registered contexts, until the daemon sends the configured one
+const DLT_USER_INITIAL_LOG_LEVEL: i8    = 4; // DLT_LOG_INFO
+const DLT_USER_INITIAL_TRACE_STATUS: i8 = 0; // DLT_TRACE_STATUS_OFF
+/// ECU ID used in the messages, the daemon usually overwrites it with its own
+const DLT_USER_DEFAULT_ECU_ID: &[u8; 4] = b"ECU1";
+/// Upper limit of the startup buffer, like `DLT_USER_RINGBUFFER_MAX_SIZE`
+const DLT_USER_RINGBUFFER_MAX_SIZE: usize = 500_000;
+/// How often the housekeeper thread retries to reach the daemon, in milliseconds
+const DLT_USER_RECONNECT_INTERVAL: c_int = 500;
+/// How long the exit handler tries to empty the startup buffer, in milliseconds
+const DLT_USER_ATEXIT_RESEND_BUFFER_EXIT_TIMEOUT: u32 = 1000;
+
+lazy_static! {
+    static ref USER: Mutex<User> = Mutex::new(User::new());
+}
+
+/// Cached outside of the lock, read for every argument written
+static VERBOSE_MODE: AtomicBool = AtomicBool::new(true);
+
+thread_local! {
+    /// Whether this thread holds `USER`, locking it once more would deadlock
+    static HOLDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `USER` locked by the current thread
+pub struct UserGuard(MutexGuard<'static, User>);
+
+impl Deref for UserGuard {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+impl DerefMut for UserGuard {
+    fn deref_mut(&mut self) -> &mut User {
+        &mut self.0
+    }
+}
+
+impl Drop for UserGuard {
+    fn drop(&mut self) {
+        HOLDING.with(|holding| holding.set(false));
+    }
+}
+
+pub fn lock() -> UserGuard {
+    let user = USER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    HOLDING.with(|holding| holding.set(true));
+    UserGuard(user)
+}
+
+/// Whether the current thread holds the lock, a panic hook can't log then
+pub fn locked_by_current_thread() -> bool {
+    HOLDING.with(Cell::get)
+}
+
+pub fn verbose_mode() -> bool {
+    VERBOSE_MODE.load(Ordering::Relaxed)
+}
+
+pub fn set_verbose_mode(verbose: bool) {
+    VERBOSE_MODE.store(verbose, Ordering::Relaxed)
+}
+
+/// Optional fields of the headers of the log messages
+pub struct HeaderOptions {
+    pub with_session_id: bool,
+    pub with_timestamp: bool,
+    pub with_ecu_id: bool,
+    pub use_extended_header_for_non_verbose: bool
+}
+
+/// A registered context, the log level lives on the heap so `DltContext` can point to it
+struct ContextEntry {
+    id: [u8; DLT_ID_SIZE],
+    description: Vec<u8>,
+    log_level: Box<i8>,
+    trace_status: Box<i8>,
+    log_level_changed: Option<LogLevelChangedCallback>
+}
+
+/// Log level change to report once the lock is released, like libdlt's delayed callback
+pub struct LevelChange {
+    callback: LogLevelChangedCallback,
+    id: [u8; DLT_ID_SIZE],
+    log_level: u8,
+    trace_status: u8
+}
+
+impl LevelChange {
+    pub fn notify(mut self) {
+        unsafe {
+            (self.callback)(self.id.as_mut_ptr() as *mut libc::c_char, self.log_level, self.trace_status);
+        }
+    }
+}
+
+/// Messages waiting for the daemon, bounded by their total size
+pub struct RingBuffer {
+    messages: VecDeque<Vec<u8>>,
+    used: usize,
+    max_size: usize,
+    /// Evict the oldest messages instead of rejecting the new one
+    drop_oldest: bool,
+    /// Messages evicted since the start of the process
+    dropped: u32
+}
+
+impl RingBuffer {
+    fn new(max_size: usize) -> RingBuffer {
+        RingBuffer { messages: VecDeque::new(), used: 0, max_size, drop_oldest: false, dropped: 0 }
+    }
+
+    fn push(&mut self, message: Vec<u8>) -> bool {
+        if message.len() > self.max_size {
+            return false;
+        }
+
+        if self.drop_oldest {
+            while self.used + message.len() > self.max_size && self.pop().is_some() {
+                self.dropped = self.dropped.wrapping_add(1);
+            }
+        } else if self.used + message.len() > self.max_size {
+            return false;
+        }
+
+        self.used += message.len();
+        self.messages.push_back(message);
+        true
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let message = self.messages.pop_front();
+        if let Some(ref message) = message {
+            self.used -= message.len();
+        }
+
+        message
+    }
+
+    fn push_front(&mut self, message: Vec<u8>) {
+        self.used += message.len();
+        self.messages.push_front(message);
+    }
+}
+
+pub struct User {
+    initialised: bool,
+    ecu_id: [u8; DLT_ID_SIZE],
+    app_id: [u8; DLT_ID_SIZE],
+    app_description: Vec<u8>,
+    contexts: Vec<Option<ContextEntry>>,
+
+    base_dir: PathBuf,
+    daemon: Option<File>,
+    user_fifo: Option<File>,
+
+    buffer: RingBuffer,
+    overflow_counter: u32,
+    /// Timeout used by the exit handler, in milliseconds
+    timeout_at_exit_handler: u32,
+
+    headers: HeaderOptions,
+    /// Print the messages on stdout while no client is connected to the daemon
+    enable_local_print: bool,
+    /// Last state sent by the daemon: -1 unknown, 0 no client connected, 1 client connected
+    log_state: i8,
+
+    /// Log messages go to this file instead of the daemon, control messages still reach
+    /// the daemon so switching back needs no new registration
+    file: Option<FileSink>,
+    file_options: SinkOptions
+}
+
+impl User {
+    fn new() -> User {
+        User {
+            initialised: false,
+            ecu_id: *DLT_USER_DEFAULT_ECU_ID,
+            app_id: [0; DLT_ID_SIZE],
+            app_description: Vec::new(),
+            contexts: Vec::new(),
+
+            base_dir: PathBuf::new(),
+            daemon: None,
+            user_fifo: None,
+
+            buffer: RingBuffer::new(DLT_USER_RINGBUFFER_MAX_SIZE),
+            overflow_counter: 0,
+            timeout_at_exit_handler: DLT_USER_ATEXIT_RESEND_BUFFER_EXIT_TIMEOUT,
+
+            headers: HeaderOptions {
+                with_session_id: true,
+                with_timestamp: true,
+                with_ecu_id: true,
+                use_extended_header_for_non_verbose: true
+            },
+            enable_local_print: true,
+            log_state: -1,
+
+            file: None,
+            file_options: SinkOptions::default()
+        }
+    }
+
+    fn init(&mut self) -> DltReturnValue {
+        if self.initialised {
+            return DltReturnValue::DLT_RETURN_OK;
+        }
+
+        // Writing to the daemon FIFO after the daemon went away must not kill the application
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN); }
+
+        self.base_dir = fifo_base_dir();
+        match self.open_user_fifo() {
+            Ok(fifo) => self.user_fifo = Some(fifo),
+            Err(_)   => return DltReturnValue::DLT_RETURN_ERROR
+        }
+
+        self.initialised = true;
+        self.attach();
+
+        let _ = thread::Builder::new().name("dlt-housekeeper".to_owned()).spawn(housekeeper);
+        unsafe { libc::atexit(atexit_blow_out_user_buffer); }
+
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    fn user_fifo_path(&self) -> PathBuf {
+        self.base_dir.join("dltpipes").join(format!("dlt{}", process::id()))
+    }
+
+    fn open_user_fifo(&self) -> io::Result<File> {
+        let dir = self.base_dir.join("dltpipes");
+        if !dir.exists() {
+            fs::create_dir_all(&dir)?;
+            // Every application creates its FIFO in here, like `/tmp`
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o1777))?;
+        }
+
+        let path = self.user_fifo_path();
+        let _ = fs::remove_file(&path);
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o620) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Read-write, so the FIFO never reports an end of file when the daemon closes it
+        OpenOptions::new().read(true).write(true)
+                          .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+                          .open(&path)
+    }
+
+    /// Tries to open the daemon FIFO, registering everything again on success
+    fn attach(&mut self) -> bool {
+        if self.daemon.is_some() {
+            return true;
+        }
+
+        let daemon = OpenOptions::new().write(true)
+                                       .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+                                       .open(self.base_dir.join("dlt"));
+        match daemon {
+            Ok(daemon) => self.daemon = Some(daemon),
+            Err(_)     => return false
+        }
+
+        if self.app_id[0] != 0 {
+            let message = self.register_app_message();
+            let _ = self.write_direct(&message);
+
+            for pos in 0..self.contexts.len() {
+                if let Some(message) = self.register_context_message(pos) {
+                    let _ = self.write_direct(&message);
+                }
+            }
+        }
+
+        self.flush();
+        self.daemon.is_some()
+    }
+
+    /// Writes a complete message to the daemon, without buffering
+    fn write_direct(&mut self, message: &[u8]) -> Result<(), DltReturnValue> {
+        let result = match self.daemon {
+            Some(ref mut daemon) => daemon.write(message),
+            None                 => return Err(DltReturnValue::DLT_RETURN_PIPE_ERROR)
+        };
+
+        match result {
+            Ok(written) if written == message.len() => Ok(()),
+            // FIFO writes up to `PIPE_BUF` are atomic, a short write doesn't happen for our sizes
+            Ok(_) => Err(DltReturnValue::DLT_RETURN_PIPE_FULL),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Err(DltReturnValue::DLT_RETURN_PIPE_FULL),
+            Err(_) => {
+                // The daemon is gone, everything is registered again once it is back
+                self.daemon = None;
+                Err(DltReturnValue::DLT_RETURN_PIPE_ERROR)
+            }
+        }
+    }
+
+    /// Sends a message, keeping it in the startup buffer if the daemon can't take it now
+    fn send(&mut self, message: Vec<u8>) -> DltReturnValue {
+        // Like `dlt_user_log_send_log`, the buffered messages go out first and this one follows
+        // directly once they all did, instead of waiting for the housekeeper
+        if self.attach() {
+            self.flush();
+            if self.buffer.messages.is_empty() && self.write_direct(&message).is_ok() {
+                return DltReturnValue::DLT_RETURN_OK;
+            }
+        }
+
+        if self.buffer.push(message) {
+            DltReturnValue::DLT_RETURN_OK
+        } else {
+            self.overflow_counter += 1;
+            DltReturnValue::DLT_RETURN_BUFFER_FULL
+        }
+    }
+
+    pub fn check_buffer(&self) -> (usize, usize) {
+        (self.buffer.max_size, self.buffer.used)
+    }
+
+    pub fn set_drop_oldest(&mut self, enable: bool) {
+        self.buffer.drop_oldest = enable;
+    }
+
+    pub fn dropped_oldest(&self) -> u32 {
+        self.buffer.dropped
+    }
+
+    pub fn buffered_messages(&self) -> usize {
+        self.buffer.messages.len()
+    }
+
+    /// Sets the level and trace status of all the contexts, and tells the daemon
+    pub fn set_application_ll_ts_limit(&mut self, log_level: i8, trace_status: i8) -> DltReturnValue {
+        if !self.initialised || self.app_id[0] == 0 {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        let mut message = user_header(protocol::USER_MESSAGE_APP_LL_TS);
+        message.extend_from_slice(&self.app_id);
+        message.push(log_level as u8);
+        message.push(trace_status as u8);
+        if let Err(ret) = self.write_direct(&message) {
+            return ret;
+        }
+
+        // Only once the daemon knows, the levels of both sides would differ otherwise
+        for entry in self.contexts.iter_mut().flatten() {
+            *entry.log_level    = log_level;
+            *entry.trace_status = trace_status;
+        }
+
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    pub fn header_options(&mut self) -> &mut HeaderOptions {
+        &mut self.headers
+    }
+
+    pub fn set_local_print(&mut self, enable: bool) {
+        self.enable_local_print = enable;
+    }
+
+    pub fn log_state(&self) -> i8 {
+        self.log_state
+    }
+
+    /// Writes the log messages to `path` from now on, the file is truncated
+    pub fn init_file(&mut self, path: &Path) -> DltReturnValue {
+        let sink = match FileSink::create(path, self.file_options) {
+            Ok(sink) => sink,
+            Err(_)   => return DltReturnValue::DLT_RETURN_ERROR
+        };
+
+        // Without a FIFO the messages still go to the file, the daemon just can't be used later
+        if self.init() != DltReturnValue::DLT_RETURN_OK {
+            self.initialised = true;
+        }
+
+        self.file = Some(sink);
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    pub fn set_file_options(&mut self, options: SinkOptions) {
+        self.file_options = options;
+        if let Some(ref mut sink) = self.file {
+            sink.set_options(options);
+        }
+    }
+
+    /// Goes back to sending the log messages to the daemon, the file is synced and closed
+    pub fn close_file(&mut self) -> DltReturnValue {
+        match self.file.take() {
+            Some(_) => DltReturnValue::DLT_RETURN_OK,
+            None    => DltReturnValue::DLT_RETURN_ERROR
+        }
+    }
+
+    /// Asks the daemon to log a marker, like libdlt it isn't buffered
+    pub fn log_marker(&mut self) -> DltReturnValue {
+        if !self.initialised || !self.attach() {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        match self.write_direct(&user_header(protocol::USER_MESSAGE_MARKER)) {
+            Ok(())   => DltReturnValue::DLT_RETURN_OK,
+            Err(ret) => ret
+        }
+    }
+
+    /// Sends the log mode to the daemon, like libdlt it isn't buffered
+    pub fn set_log_mode(&mut self, mode: i8) -> DltReturnValue {
+        if !self.initialised && self.init() != DltReturnValue::DLT_RETURN_OK {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        let mut message = user_header(protocol::USER_MESSAGE_LOG_MODE);
+        message.push(mode as u8);
+        match self.write_direct(&message) {
+            Ok(())   => DltReturnValue::DLT_RETURN_OK,
+            Err(ret) => ret
+        }
+    }
+
+    pub fn set_resend_timeout_atexit(&mut self, timeout: u32) {
+        self.timeout_at_exit_handler = timeout;
+    }
+
+    pub fn resend_buffer(&mut self) -> DltReturnValue {
+        if !self.initialised || !self.attach() {
+            return DltReturnValue::DLT_RETURN_PIPE_ERROR;
+        }
+
+        self.flush();
+        if self.buffer.messages.is_empty() {
+            DltReturnValue::DLT_RETURN_OK
+        } else {
+            DltReturnValue::DLT_RETURN_PIPE_FULL
+        }
+    }
+
+    /// Sends the buffered messages, followed by the overflow notification if messages were lost
+    fn flush(&mut self) {
+        while let Some(message) = self.buffer.pop() {
+            if self.write_direct(&message).is_err() {
+                self.buffer.push_front(message);
+                return;
+            }
+        }
+
+        if self.overflow_counter > 0 {
+            let mut message = user_header(protocol::USER_MESSAGE_OVERFLOW);
+            message.extend_from_slice(&self.overflow_counter.to_ne_bytes());
+            message.extend_from_slice(&self.app_id);
+            if self.write_direct(&message).is_ok() {
+                self.overflow_counter = 0;
+            }
+        }
+    }
+
+    fn register_app_message(&self) -> Vec<u8> {
+        let mut message = user_header(protocol::USER_MESSAGE_REGISTER_APPLICATION);
+        message.extend_from_slice(&self.app_id);
+        message.extend_from_slice(&(process::id() as i32).to_ne_bytes());
+        message.extend_from_slice(&(self.app_description.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&self.app_description);
+        message
+    }
+
+    fn register_context_message(&self, pos: usize) -> Option<Vec<u8>> {
+        let entry = self.contexts[pos].as_ref()?;
+
+        let mut message = user_header(protocol::USER_MESSAGE_REGISTER_CONTEXT);
+        message.extend_from_slice(&self.app_id);
+        message.extend_from_slice(&entry.id);
+        message.extend_from_slice(&(pos as i32).to_ne_bytes());
+        message.push(*entry.log_level as u8);
+        message.push(*entry.trace_status as u8);
+        message.extend_from_slice(&(process::id() as i32).to_ne_bytes());
+        message.extend_from_slice(&(entry.description.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&entry.description);
+        Some(message)
+    }
+
+    pub fn register_app(&mut self, app_id: &[u8], description: &[u8]) -> DltReturnValue {
+        let ret = self.init();
+        if ret != DltReturnValue::DLT_RETURN_OK {
+            return ret;
+        }
+
+        self.app_id          = to_id(app_id);
+        self.app_description = description.to_vec();
+
+        let message = self.register_app_message();
+        if self.attach() {
+            let _ = self.write_direct(&message);
+        }
+
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    pub fn unregister_app(&mut self) -> DltReturnValue {
+        if !self.initialised || self.app_id[0] == 0 {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        self.flush();
+
+        let mut message = user_header(protocol::USER_MESSAGE_UNREGISTER_APPLICATION);
+        message.extend_from_slice(&self.app_id);
+        message.extend_from_slice(&(process::id() as i32).to_ne_bytes());
+        let _ = self.write_direct(&message);
+
+        self.app_id = [0; DLT_ID_SIZE];
+        self.app_description.clear();
+
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    pub fn register_context(&mut self, handle: &mut DltContext, context_id: &[u8], description: &[u8],
+                            log_level: i8, trace_status: i8) -> DltReturnValue {
+        if !self.initialised || self.app_id[0] == 0 {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        let id = to_id(context_id);
+        if self.contexts.iter().flatten().any(|entry| entry.id == id) {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        // Log level and trace status given at registration overwrite the initial values
+        let mut entry = ContextEntry {
+            id,
+            description: description.to_vec(),
+            log_level: Box::new(if log_level >= 0 { log_level } else { DLT_USER_INITIAL_LOG_LEVEL }),
+            trace_status: Box::new(if trace_status >= 0 { trace_status } else { DLT_USER_INITIAL_TRACE_STATUS }),
+            log_level_changed: None
+        };
+
+        let pos = match self.contexts.iter().position(|entry| entry.is_none()) {
+            Some(pos) => pos,
+            None      => {
+                self.contexts.push(None);
+                self.contexts.len() - 1
+            }
+        };
+
+        for (dst, &src) in handle.contextID.iter_mut().zip(id.iter()) {
+            *dst = src as libc::c_char;
+        }
+        handle.log_level_pos    = pos as i32;
+        handle.log_level_ptr    = &mut *entry.log_level;
+        handle.trace_status_ptr = &mut *entry.trace_status;
+        handle.mcnt             = 0;
+
+        self.contexts[pos] = Some(entry);
+
+        // The daemon sends back the log level configured for the context
+        if self.attach() {
+            if let Some(message) = self.register_context_message(pos) {
+                let _ = self.write_direct(&message);
+            }
+        }
+
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    pub fn unregister_context(&mut self, handle: &mut DltContext) -> DltReturnValue {
+        let pos = handle.log_level_pos as usize;
+        let id = match self.contexts.get(pos) {
+            Some(Some(entry)) => entry.id,
+            _ => return DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+        };
+
+        handle.log_level_ptr    = ptr::null_mut();
+        handle.trace_status_ptr = ptr::null_mut();
+        self.contexts[pos] = None;
+
+        let mut message = user_header(protocol::USER_MESSAGE_UNREGISTER_CONTEXT);
+        message.extend_from_slice(&self.app_id);
+        message.extend_from_slice(&id);
+        message.extend_from_slice(&(process::id() as i32).to_ne_bytes());
+        let _ = self.write_direct(&message);
+
+        DltReturnValue::DLT_RETURN_OK
+    }
+
+    pub fn set_log_level_changed_callback(&mut self, handle: &DltContext,
+                                          callback: Option<LogLevelChangedCallback>) -> DltReturnValue {
+        match self.contexts.get_mut(handle.log_level_pos as usize) {
+            Some(Some(entry)) => {
+                entry.log_level_changed = callback;
+                DltReturnValue::DLT_RETURN_OK
+            },
+            _ => DltReturnValue::DLT_RETURN_WRONG_PARAMETER
+        }
+    }
+
+    pub fn send_log(&mut self, log: &mut DltContextData) -> DltReturnValue {
+        if !self.initialised || self.app_id[0] == 0 {
+            return DltReturnValue::DLT_RETURN_ERROR;
+        }
+
+        let handle  = unsafe { &mut *log.handle };
+        let verbose = verbose_mode();
+
+        let mut htyp = HTYP_VERSION1;
+        if cfg!(target_endian = "big") {
+            htyp |= HTYP_MSBF;
+        }
+
+        let mut extra = Vec::with_capacity(12);
+        if self.headers.with_ecu_id {
+            htyp |= HTYP_WEID;
+            extra.extend_from_slice(&self.ecu_id);
+        }
+        if self.headers.with_session_id {
+            htyp |= HTYP_WSID;
+            extra.extend_from_slice(&process::id().to_be_bytes());
+        }
+        if self.headers.with_timestamp {
+            htyp |= HTYP_WTMS;
+            extra.extend_from_slice(&uptime().to_be_bytes());
+        }
+        if verbose || self.headers.use_extended_header_for_non_verbose {
+            htyp |= HTYP_UEH;
+            let verb = if verbose { MSIN_VERB } else { 0 };
+            extra.push(verb | (MSTP_LOG << 1) | ((log.log_level as u8) << 4));
+            extra.push(log.args_num as u8);
+            extra.extend_from_slice(&self.app_id);
+            for &byte in &handle.contextID {
+                extra.push(byte as u8);
+            }
+        }
+
+        let payload = &log.buffer[..log.size as usize];
+        let len     = (4 + extra.len() + payload.len()) as u16;
+
+        let mut message = user_header(protocol::USER_MESSAGE_LOG);
+        message.push(htyp);
+        message.push(handle.mcnt);
+        message.extend_from_slice(&len.to_be_bytes());
+        message.extend_from_slice(&extra);
+        message.extend_from_slice(payload);
+
+        handle.mcnt = handle.mcnt.wrapping_add(1);
+
+        if self.enable_local_print && self.log_state == 0 {
+            print_locally(&message[protocol::USER_HEADER_SIZE..]);
+        }
+
+        if let Some(ref mut sink) = self.file {
+            return match sink.write(&self.ecu_id, &message[protocol::USER_HEADER_SIZE..]) {
+                Ok(()) => DltReturnValue::DLT_RETURN_OK,
+                Err(_) => DltReturnValue::DLT_RETURN_ERROR
+            };
+        }
+
+        self.send(message)
+    }
+
+    /// Handles the messages the daemon wrote to the application FIFO.
+    ///
+    /// ### Returns
+    /// The log level changes to report, after releasing the lock
+    fn receive(&mut self, pending: &mut Vec<u8>) -> Vec<LevelChange> {
+        let mut changes = Vec::new();
+        let mut chunk = [0u8; 1024];
+        loop {
+            let read = match self.user_fifo {
+                Some(ref mut fifo) => fifo.read(&mut chunk),
+                None               => return changes
+            };
+
+            match read {
+                Ok(read) if read > 0 => pending.extend_from_slice(&chunk[..read]),
+                _ => break
+            }
+        }
+
+        let mut consumed = 0;
+        while pending.len() - consumed >= protocol::USER_HEADER_SIZE {
+            let message = &pending[consumed..];
+            if &message[..4] != protocol::USER_HEADER_PATTERN {
+                consumed += 1;
+                continue;
+            }
+
+            let mut kind = [0u8; 4];
+            kind.copy_from_slice(&message[4..8]);
+            let body = &message[protocol::USER_HEADER_SIZE..];
+
+            let size = match u32::from_ne_bytes(kind) {
+                protocol::USER_MESSAGE_LOG_LEVEL => {
+                    // log_level(u8), trace_status(u8), log_level_pos(i32)
+                    if body.len() < 6 {
+                        break;
+                    }
+
+                    let mut pos = [0u8; 4];
+                    pos.copy_from_slice(&body[2..6]);
+                    if let Some(&mut Some(ref mut entry)) = self.contexts.get_mut(i32::from_ne_bytes(pos) as usize) {
+                        *entry.log_level    = body[0] as i8;
+                        *entry.trace_status = body[1] as i8;
+                        if let Some(callback) = entry.log_level_changed {
+                            changes.push(LevelChange { callback, id: entry.id, log_level: body[0], trace_status: body[1] });
+                        }
+                    }
+                    6
+                },
+                protocol::USER_MESSAGE_INJECTION => {
+                    // log_level_pos(i32), service_id(u32), data_length_inject(u32), data
+                    if body.len() < 12 {
+                        break;
+                    }
+
+                    let mut len = [0u8; 4];
+                    len.copy_from_slice(&body[8..12]);
+                    let len = u32::from_ne_bytes(len) as usize;
+                    if body.len() < 12 + len {
+                        break;
+                    }
+                    12 + len
+                },
+                protocol::USER_MESSAGE_LOG_STATE => {
+                    if body.is_empty() {
+                        break;
+                    }
+                    self.log_state = body[0] as i8;
+                    1
+                },
+                _ => 0
+            };
+
+            consumed += protocol::USER_HEADER_SIZE + size;
+        }
+
+        pending.drain(..consumed);
+        changes
+    }
+}
+
+/// Reads the daemon's messages and keeps retrying to send buffered messages
+fn housekeeper() {
+    let mut pending = Vec::new();
+    loop {
+        let fd = match lock().user_fifo {
+            Some(ref fifo) => fifo.as_raw_fd(),
+            None           => return
+        };
+
+        let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        unsafe { libc::poll(&mut poll_fd, 1, DLT_USER_RECONNECT_INTERVAL); }
+
+        let changes = {
+            let mut user = lock();
+            if !user.initialised {
+                return;
+            }
+
+            let changes = user.receive(&mut pending);
+            if user.attach() {
+                user.flush();
+            }
+            changes
+        };
+
+        for change in changes {
+            change.notify();
+        }
+    }
+}
+
+/// Tries to send the remaining buffered messages when the process exits, like libdlt does
+extern "C" fn atexit_blow_out_user_buffer() {
+    // Never wait for a thread that still holds the lock while the process is going down
+    let timeout = match USER.try_lock() {
+        Ok(user) if user.initialised && !user.buffer.messages.is_empty() => user.timeout_at_exit_handler,
+        _ => return
+    };
+
+    let deadline = Instant::now() + Duration::from_millis(timeout as u64);
+    while Instant::now() < deadline {
+        if let Ok(mut user) = USER.try_lock() {
+            if user.resend_buffer() == DltReturnValue::DLT_RETURN_OK {
+                return;
+            }
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Prints a log message on stdout, the way `dlt_message_print_ascii` shows it
+fn print_locally(message: &[u8]) {
+    if let protocol::Parsed::Complete(record, _) = protocol::parse_record(message) {
+        let level = record.level.map_or(String::new(), |level| level.to_string());
+        println!("{} {} {} {} log {} {}", record.timestamp.unwrap_or(0), record.ecu_id.as_deref().unwrap_or(""),
+                 record.app_id, record.context_id, level, record.text());
+    }
+}
+
+fn user_header(kind: u32) -> Vec<u8> {
+    let mut message = Vec::with_capacity(64);
+    message.extend_from_slice(protocol::USER_HEADER_PATTERN);
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message
+}
+
+fn to_id(id: &[u8]) -> [u8; DLT_ID_SIZE] {
+    let mut result = [0; DLT_ID_SIZE];
+    for (dst, &src) in result.iter_mut().zip(id.iter()) {
+        *dst = src;
+    }
+
+    result
+}
+
+fn fifo_base_dir() -> PathBuf {
+    let base_dir = unsafe { CStr::from_ptr(ptr::addr_of!(dltFifoBaseDir) as *const libc::c_char) };
+    if base_dir.to_bytes().is_empty() {
+        PathBuf::from("/tmp")
+    } else {
+        PathBuf::from(OsStr::from_bytes(base_dir.to_bytes()))
+    }
+}
+
+/// Time since system start in 0.1 milliseconds, like `dlt_uptime`
+fn uptime() -> u32 {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now); }
+
+    (now.tv_sec as u64 * 10_000 + now.tv_nsec as u64 / 100_000) as u32
+}
+
+#[test]
+fn ring_buffer_overflow() {
+    let mut buffer = RingBuffer::new(10);
+    assert!(buffer.push(vec![1; 4]));
+    assert!(buffer.push(vec![2; 4]));
+    assert!(!buffer.push(vec![3; 4]));
+
+    buffer.drop_oldest = true;
+    assert!(buffer.push(vec![3; 4]));
+    assert_eq!(buffer.dropped, 1);
+    assert_eq!(buffer.used, 8);
+    assert_eq!(buffer.pop(), Some(vec![2; 4]));
+}
+
+#[test]
+fn send_flushes_the_buffer_first() {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+
+    let mut user = User::new();
+    user.daemon = Some(unsafe { File::from_raw_fd(fds[1]) });
+    assert!(user.buffer.push(b"buffered".to_vec()));
+
+    assert_eq!(user.send(b" direct".to_vec()), DltReturnValue::DLT_RETURN_OK);
+    assert!(user.buffer.messages.is_empty());
+
+    let mut received = [0; 15];
+    reader.read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"buffered direct");
+}
@@ -0,0 +1,169 @@
+//! Reporting panics to the daemon.
+//!
+//! A panic only prints to stderr, which is usually gone on a target. The hook logs it as
+//! `Fatal` verbose messages: one with the thread, location and panic message, then the
+//! backtrace split over as many messages as needed to fit in `DLT_USER_BUF_MAX_SIZE`.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::panic::{ self, PanicHookInfo };
+use std::thread;
+
+use ffi;
+use { Context, Level };
+
+/// Room left in each message for the argument headers and the fixed arguments
+const MESSAGE_OVERHEAD: usize = 64;
+/// Type info, length and terminating 0 of a string argument
+const STRING_OVERHEAD: usize = 7;
+
+fn max_text_size() -> usize {
+    ffi::DLT_USER_BUF_MAX_SIZE - MESSAGE_OVERHEAD
+}
+
+/// Installs a panic hook logging the panics of all threads to `context`.
+///
+/// The previously installed hook runs afterwards, so when this is called after `init` the
+/// flush done by the `FlushGuard`'s hook sends the panic messages too.
+pub fn install_panic_hook(context: &Context) {
+    let context  = context.clone();
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        log_panic(&context, info);
+        previous(info);
+    }));
+}
+
+fn payload_text(payload: &(dyn Any + Send)) -> &str {
+    if let Some(text) = payload.downcast_ref::<&str>() {
+        text
+    } else if let Some(text) = payload.downcast_ref::<String>() {
+        text
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+// The user library is a mutex with `native`, locking it again from the thread holding it
+// would deadlock
+#[cfg(feature = "native")]
+fn user_library_locked() -> bool {
+    ffi::dlt_user_is_locked_by_current_thread()
+}
+
+#[cfg(not(feature = "native"))]
+fn user_library_locked() -> bool {
+    false
+}
+
+/// Thread, location and payload cut to fit in one message after `"panicked"`, the thread name
+/// gets at most a quarter of the room and the location half of what is left
+fn fit_panic_message<'a>(thread: &'a str, location: &'a str, payload: &'a str) -> [&'a str; 3] {
+    let mut available = ffi::DLT_USER_BUF_MAX_SIZE - 4 * STRING_OVERHEAD - "panicked".len();
+    let thread   = truncate(thread, available / 4);
+    available -= thread.len();
+    let location = truncate(location, available / 2);
+    available -= location.len();
+
+    [thread, location, truncate(payload, available)]
+}
+
+fn log_panic(context: &Context, info: &PanicHookInfo) {
+    if user_library_locked() {
+        return;
+    }
+
+    let thread   = thread::current();
+    let location = match info.location() {
+        Some(location) => format!("{}:{}:{}", location.file(), location.line(), location.column()),
+        None           => String::from("<unknown>")
+    };
+
+    let [thread, location, payload] = fit_panic_message(thread.name().unwrap_or("<unnamed>"), &location,
+                                                        payload_text(info.payload()));
+    if let Some(mut message) = context.message(Level::Fatal) {
+        let _ = message.string("panicked")
+                       .string(thread)
+                       .string(location)
+                       .string(payload)
+                       .send();
+    }
+
+    let backtrace = Backtrace::force_capture().to_string();
+    let chunks    = split_lines(&backtrace, max_text_size());
+    for (index, chunk) in chunks.iter().enumerate() {
+        if let Some(mut message) = context.message(Level::Fatal) {
+            let _ = message.string("backtrace")
+                           .u16(index as u16 + 1)
+                           .u16(chunks.len() as u16)
+                           .string(chunk)
+                           .send();
+        }
+    }
+}
+
+/// Cuts `text` to at most `max` bytes, on a character boundary
+fn truncate(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Groups the lines of `text` in chunks of at most `max` bytes, longer lines are cut
+fn split_lines(text: &str, max: usize) -> Vec<String> {
+    let mut chunks  = Vec::new();
+    let mut current = String::new();
+
+    for line in text.lines() {
+        let mut line = line;
+        while !line.is_empty() || current.is_empty() {
+            let separator = if current.is_empty() { 0 } else { 1 };
+            if current.len() + separator + line.len() <= max {
+                if separator == 1 {
+                    current.push('\n');
+                }
+                current.push_str(line);
+                break;
+            }
+
+            if current.is_empty() {
+                let head = truncate(line, max);
+                chunks.push(head.to_owned());
+                line = &line[head.len()..];
+            } else {
+                chunks.push(current.split_off(0));
+            }
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+#[test]
+fn long_panic_messages_fit() {
+    let long = "y".repeat(2 * ffi::DLT_USER_BUF_MAX_SIZE);
+    let [thread, location, payload] = fit_panic_message(&long, &long, &long);
+    let size = ["panicked", thread, location, payload].iter().map(|text| text.len() + STRING_OVERHEAD).sum::<usize>();
+
+    assert!(size <= ffi::DLT_USER_BUF_MAX_SIZE);
+    assert!(!thread.is_empty() && !location.is_empty() && !payload.is_empty());
+    assert_eq!(fit_panic_message("main", "src/main.rs:1:1", "oops"), ["main", "src/main.rs:1:1", "oops"]);
+}
+
+#[test]
+fn backtrace_is_split_on_lines() {
+    let text   = "frame 0\nframe 1\n".to_owned() + &"x".repeat(25) + "\nframe 3";
+    let chunks = split_lines(&text, 16);
+
+    assert_eq!(chunks, vec!["frame 0\nframe 1", "xxxxxxxxxxxxxxxx", "xxxxxxxxx", "frame 3"]);
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 16));
+}
@@ -0,0 +1,551 @@
+//! Wire format of the DLT protocol: the messages an application sends
+//! to the daemon over the FIFO, the DLT messages themselves and the storage
+//! header preceding them in `.dlt` files.
+//!
+//! Apart from the storage header, only decoding is needed by the helpers in this
+//! crate, the values follow `dlt_protocol.h`, `dlt_common.h` and `dlt_user_shared_cfg.h`.
+
+use std::fmt;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use { id_to_string, Level };
+
+/// Pattern starting every message between the user library and the daemon("DUH" + 0x01)
+pub const USER_HEADER_PATTERN: &[u8; 4] = b"DUH\x01";
+/// Size of `DltUserHeader`
+pub const USER_HEADER_SIZE: usize = 8;
+
+/// Pattern starting every message stored in a `.dlt` file("DLT" + 0x01)
+pub const STORAGE_HEADER_PATTERN: &[u8; 4] = b"DLT\x01";
+/// Size of `DltStorageHeader`
+pub const STORAGE_HEADER_SIZE: usize = 16;
+
+pub const USER_MESSAGE_LOG: u32                     = 1;
+pub const USER_MESSAGE_REGISTER_APPLICATION: u32    = 2;
+pub const USER_MESSAGE_UNREGISTER_APPLICATION: u32  = 3;
+pub const USER_MESSAGE_REGISTER_CONTEXT: u32        = 4;
+pub const USER_MESSAGE_UNREGISTER_CONTEXT: u32      = 5;
+pub const USER_MESSAGE_LOG_LEVEL: u32               = 6;
+pub const USER_MESSAGE_INJECTION: u32               = 7;
+pub const USER_MESSAGE_OVERFLOW: u32                = 8;
+pub const USER_MESSAGE_APP_LL_TS: u32               = 9;
+pub const USER_MESSAGE_LOG_SHM: u32                 = 10;
+pub const USER_MESSAGE_LOG_MODE: u32                = 11;
+pub const USER_MESSAGE_LOG_STATE: u32               = 12;
+pub const USER_MESSAGE_MARKER: u32                  = 13;
+
+/// Use extended header
+pub const HTYP_UEH: u8  = 0x01;
+/// Payload is big endian
+pub const HTYP_MSBF: u8 = 0x02;
+/// With ECU ID
+pub const HTYP_WEID: u8 = 0x04;
+/// With session ID
+pub const HTYP_WSID: u8 = 0x08;
+/// With timestamp
+pub const HTYP_WTMS: u8 = 0x10;
+/// Protocol version 1
+pub const HTYP_VERSION1: u8 = 0x20;
+
+/// Verbose message
+pub const MSIN_VERB: u8 = 0x01;
+/// Message type "log", stored in the MSTP bits of the message info
+pub const MSTP_LOG: u8  = 0x00;
+
+pub const TYPE_INFO_TYLE: u32 = 0x0000_000f;
+pub const TYPE_INFO_TYLE_32BIT: u32 = 0x0000_0003;
+pub const TYPE_INFO_BOOL: u32 = 0x0000_0010;
+pub const TYPE_INFO_SINT: u32 = 0x0000_0020;
+pub const TYPE_INFO_UINT: u32 = 0x0000_0040;
+pub const TYPE_INFO_FLOA: u32 = 0x0000_0080;
+pub const TYPE_INFO_ARAY: u32 = 0x0000_0100;
+pub const TYPE_INFO_STRG: u32 = 0x0000_0200;
+pub const TYPE_INFO_RAWD: u32 = 0x0000_0400;
+pub const TYPE_INFO_VARI: u32 = 0x0000_0800;
+pub const TYPE_INFO_FIXP: u32 = 0x0000_1000;
+pub const TYPE_INFO_TRAI: u32 = 0x0000_2000;
+pub const TYPE_INFO_STRU: u32 = 0x0000_4000;
+pub const TYPE_INFO_SCOD_UTF8: u32 = 0x0000_8000;
+
+/// Result of decoding a message from the beginning of a byte stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum Parsed<T> {
+    /// A message and the number of bytes it used
+    Complete(T, usize),
+    /// More bytes are needed
+    Incomplete,
+    /// The stream is not aligned on a message, skip this many bytes
+    Garbage(usize)
+}
+
+/// Value of a verbose argument
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    Raw(Vec<u8>)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Bool(value)       => write!(f, "{}", value),
+            Value::Unsigned(value)   => write!(f, "{}", value),
+            Value::Signed(value)     => write!(f, "{}", value),
+            Value::Float(value)      => write!(f, "{}", value),
+            Value::String(ref value) => f.write_str(value),
+            Value::Raw(ref value)    => {
+                for (index, byte) in value.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A decoded verbose argument, `name` is set when the VARI bit is present
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argument {
+    pub name: Option<String>,
+    pub value: Value
+}
+
+/// A decoded DLT message(standard header, extra parameters, extended header and payload)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Message counter
+    pub counter: u8,
+    pub ecu_id: Option<String>,
+    pub session_id: Option<u32>,
+    /// Timestamp since system start in 0.1 milliseconds
+    pub timestamp: Option<u32>,
+    pub app_id: String,
+    pub context_id: String,
+    /// Set for verbose log messages only
+    pub level: Option<Level>,
+    pub verbose: bool,
+    /// Decoded arguments of a verbose message, empty for non-verbose messages
+    pub arguments: Vec<Argument>,
+    /// Raw payload
+    pub payload: Vec<u8>
+}
+
+impl Record {
+    /// All arguments joined by spaces, the way DLT viewers display a verbose message
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for argument in &self.arguments {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&argument.value.to_string());
+        }
+
+        text
+    }
+}
+
+/// A message read from a `.dlt` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRecord {
+    /// Time the message was stored, since the epoch
+    pub seconds: u32,
+    pub microseconds: i32,
+    /// ECU ID of the storage header
+    pub ecu_id: String,
+    pub record: Record
+}
+
+/// Messages sent by the user library to the daemon FIFO
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserMessage {
+    Log(Record),
+    RegisterApplication { app_id: String, pid: i32, description: String },
+    UnregisterApplication { app_id: String, pid: i32 },
+    RegisterContext {
+        app_id: String,
+        context_id: String,
+        log_level_pos: i32,
+        log_level: i8,
+        trace_status: i8,
+        pid: i32,
+        description: String
+    },
+    UnregisterContext { app_id: String, context_id: String, pid: i32 },
+    Overflow { counter: u32, app_id: String },
+    AppLogLevel { app_id: String, log_level: u8, trace_status: u8 },
+    LogMode(i8),
+    Marker,
+    /// A message type the decoder does not know, its size can't be determined
+    Unsupported(u32)
+}
+
+/// Little cursor over a byte slice, all reads are bounds checked
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8], big_endian: bool) -> Reader<'a> {
+        Reader { buf, pos: 0, big_endian }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return None;
+        }
+
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Option<u64> {
+        let bytes = self.bytes(len)?;
+        let mut value = 0u64;
+        if self.big_endian {
+            for &byte in bytes {
+                value = (value << 8) | byte as u64;
+            }
+        } else {
+            for &byte in bytes.iter().rev() {
+                value = (value << 8) | byte as u64;
+            }
+        }
+
+        Some(value)
+    }
+
+    fn u8(&mut self) -> Option<u8>   { self.uint(1).map(|value| value as u8) }
+    fn u16(&mut self) -> Option<u16> { self.uint(2).map(|value| value as u16) }
+    fn u32(&mut self) -> Option<u32> { self.uint(4).map(|value| value as u32) }
+
+    fn id(&mut self) -> Option<String> {
+        self.bytes(4).map(id_to_string)
+    }
+
+    fn text(&mut self, len: usize) -> Option<String> {
+        let bytes = self.bytes(len)?;
+        let len   = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+        Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+}
+
+#[cfg(target_endian = "big")]
+const HOST_BIG_ENDIAN: bool = true;
+#[cfg(target_endian = "little")]
+const HOST_BIG_ENDIAN: bool = false;
+
+/// Decodes one message written by the user library to the daemon FIFO.
+///
+/// Control messages use the host byte order, like the C structures they come from.
+pub fn parse_user_message(buf: &[u8]) -> Parsed<UserMessage> {
+    if buf.len() < USER_HEADER_SIZE {
+        return if USER_HEADER_PATTERN.starts_with(buf) { Parsed::Incomplete } else { Parsed::Garbage(1) };
+    }
+
+    if &buf[..4] != USER_HEADER_PATTERN {
+        return Parsed::Garbage(1);
+    }
+
+    let mut reader = Reader::new(&buf[4..], HOST_BIG_ENDIAN);
+    let kind = reader.u32().unwrap();
+
+    if kind == USER_MESSAGE_LOG {
+        return match parse_record(&buf[USER_HEADER_SIZE..]) {
+            Parsed::Complete(record, size) => Parsed::Complete(UserMessage::Log(record), USER_HEADER_SIZE + size),
+            Parsed::Incomplete             => Parsed::Incomplete,
+            Parsed::Garbage(_)             => Parsed::Garbage(1)
+        };
+    }
+
+    let message = match kind {
+        USER_MESSAGE_REGISTER_APPLICATION => (|| {
+            let app_id = reader.id()?;
+            let pid    = reader.u32()? as i32;
+            let len    = reader.u32()? as usize;
+            Some(UserMessage::RegisterApplication { app_id, pid, description: reader.text(len)? })
+        })(),
+        USER_MESSAGE_UNREGISTER_APPLICATION => (|| {
+            Some(UserMessage::UnregisterApplication { app_id: reader.id()?, pid: reader.u32()? as i32 })
+        })(),
+        USER_MESSAGE_REGISTER_CONTEXT => (|| {
+            let app_id        = reader.id()?;
+            let context_id    = reader.id()?;
+            let log_level_pos = reader.u32()? as i32;
+            let log_level     = reader.u8()? as i8;
+            let trace_status  = reader.u8()? as i8;
+            let pid           = reader.u32()? as i32;
+            let len           = reader.u32()? as usize;
+            Some(UserMessage::RegisterContext {
+                app_id,
+                context_id,
+                log_level_pos,
+                log_level,
+                trace_status,
+                pid,
+                description: reader.text(len)?
+            })
+        })(),
+        USER_MESSAGE_UNREGISTER_CONTEXT => (|| {
+            Some(UserMessage::UnregisterContext {
+                app_id: reader.id()?,
+                context_id: reader.id()?,
+                pid: reader.u32()? as i32
+            })
+        })(),
+        USER_MESSAGE_OVERFLOW => (|| {
+            Some(UserMessage::Overflow { counter: reader.u32()?, app_id: reader.id()? })
+        })(),
+        USER_MESSAGE_APP_LL_TS => (|| {
+            Some(UserMessage::AppLogLevel { app_id: reader.id()?, log_level: reader.u8()?, trace_status: reader.u8()? })
+        })(),
+        USER_MESSAGE_LOG_MODE => reader.u8().map(|mode| UserMessage::LogMode(mode as i8)),
+        USER_MESSAGE_MARKER   => Some(UserMessage::Marker),
+        _                     => Some(UserMessage::Unsupported(kind))
+    };
+
+    match message {
+        Some(message) => Parsed::Complete(message, 4 + reader.pos),
+        None          => Parsed::Incomplete
+    }
+}
+
+/// Decodes a DLT message starting with the standard header(without storage header)
+pub fn parse_record(buf: &[u8]) -> Parsed<Record> {
+    if buf.len() < 4 {
+        return Parsed::Incomplete;
+    }
+
+    let htyp = buf[0];
+    let len  = ((buf[2] as usize) << 8) | buf[3] as usize;
+    if len < 4 {
+        return Parsed::Garbage(1);
+    }
+    if buf.len() < len {
+        return Parsed::Incomplete;
+    }
+
+    let mut reader = Reader::new(&buf[..len], true);
+    reader.pos = 4;
+
+    let record = (|| {
+        let ecu_id     = if htyp & HTYP_WEID != 0 { Some(reader.id()?) } else { None };
+        let session_id = if htyp & HTYP_WSID != 0 { Some(reader.u32()?) } else { None };
+        let timestamp  = if htyp & HTYP_WTMS != 0 { Some(reader.u32()?) } else { None };
+
+        let (msin, app_id, context_id) = if htyp & HTYP_UEH != 0 {
+            let msin = reader.u8()?;
+            let _noar = reader.u8()?;
+            (msin, reader.id()?, reader.id()?)
+        } else {
+            (0, String::new(), String::new())
+        };
+
+        let verbose = msin & MSIN_VERB != 0;
+        let level   = if (msin >> 1) & 0x07 == MSTP_LOG { Level::from_raw(msin >> 4) } else { None };
+        let payload = reader.bytes(len - reader.pos)?.to_vec();
+        let arguments = if verbose {
+            parse_arguments(&payload, htyp & HTYP_MSBF != 0)
+        } else {
+            Vec::new()
+        };
+
+        Some(Record {
+            counter: buf[1],
+            ecu_id,
+            session_id,
+            timestamp,
+            app_id,
+            context_id,
+            level,
+            verbose,
+            arguments,
+            payload
+        })
+    })();
+
+    match record {
+        Some(record) => Parsed::Complete(record, len),
+        None         => Parsed::Garbage(1)
+    }
+}
+
+/// Storage header of a message stored at `time`, in little endian like `DltStorageHeader`
+pub fn storage_header(time: SystemTime, ecu_id: &[u8]) -> [u8; STORAGE_HEADER_SIZE] {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    let mut header = [0u8; STORAGE_HEADER_SIZE];
+    header[..4].copy_from_slice(STORAGE_HEADER_PATTERN);
+    header[4..8].copy_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+    header[8..12].copy_from_slice(&(since_epoch.subsec_micros() as i32).to_le_bytes());
+    for (dst, &src) in header[12..].iter_mut().zip(ecu_id) {
+        *dst = src;
+    }
+
+    header
+}
+
+/// Decodes a message of a `.dlt` file, storage header included
+pub fn parse_stored_record(buf: &[u8]) -> Parsed<StoredRecord> {
+    if buf.len() < STORAGE_HEADER_SIZE {
+        return if STORAGE_HEADER_PATTERN.starts_with(&buf[..buf.len().min(4)]) { Parsed::Incomplete } else { Parsed::Garbage(1) };
+    }
+
+    if &buf[..4] != STORAGE_HEADER_PATTERN {
+        return Parsed::Garbage(1);
+    }
+
+    let mut reader   = Reader::new(&buf[4..STORAGE_HEADER_SIZE], false);
+    let seconds      = reader.u32().unwrap();
+    let microseconds = reader.u32().unwrap() as i32;
+    let ecu_id       = reader.id().unwrap();
+
+    match parse_record(&buf[STORAGE_HEADER_SIZE..]) {
+        Parsed::Complete(record, size) => {
+            Parsed::Complete(StoredRecord { seconds, microseconds, ecu_id, record }, STORAGE_HEADER_SIZE + size)
+        },
+        Parsed::Incomplete => Parsed::Incomplete,
+        Parsed::Garbage(_) => Parsed::Garbage(1)
+    }
+}
+
+/// Decodes the arguments of a verbose payload.
+///
+/// Decoding stops at the first argument type that isn't supported(arrays, structures, fixed point).
+pub fn parse_arguments(payload: &[u8], big_endian: bool) -> Vec<Argument> {
+    let mut reader    = Reader::new(payload, big_endian);
+    let mut arguments = Vec::new();
+
+    while reader.pos < payload.len() {
+        match parse_argument(&mut reader) {
+            Some(argument) => arguments.push(argument),
+            None           => break
+        }
+    }
+
+    arguments
+}
+
+/// Decodes the arguments of a payload, `None` unless it is made of supported arguments only
+pub fn parse_all_arguments(payload: &[u8], big_endian: bool) -> Option<Vec<Argument>> {
+    let mut reader    = Reader::new(payload, big_endian);
+    let mut arguments = Vec::new();
+
+    while reader.pos < payload.len() {
+        arguments.push(parse_argument(&mut reader)?);
+    }
+
+    Some(arguments)
+}
+
+fn parse_argument(reader: &mut Reader) -> Option<Argument> {
+    let type_info = reader.u32()?;
+    let vari      = type_info & TYPE_INFO_VARI != 0;
+    let width     = match type_info & TYPE_INFO_TYLE {
+        1 => 1,
+        2 => 2,
+        3 => 4,
+        4 => 8,
+        _ => 0
+    };
+
+    if type_info & (TYPE_INFO_ARAY | TYPE_INFO_FIXP | TYPE_INFO_TRAI | TYPE_INFO_STRU) != 0 {
+        return None;
+    }
+
+    if type_info & (TYPE_INFO_STRG | TYPE_INFO_RAWD) != 0 {
+        let len  = reader.u16()? as usize;
+        let name = if vari {
+            let name_len = reader.u16()? as usize;
+            Some(reader.text(name_len)?)
+        } else {
+            None
+        };
+
+        let value = if type_info & TYPE_INFO_STRG != 0 {
+            Value::String(reader.text(len)?)
+        } else {
+            Value::Raw(reader.bytes(len)?.to_vec())
+        };
+
+        return Some(Argument { name, value });
+    }
+
+    if width == 0 {
+        return None;
+    }
+
+    let name = if vari {
+        let name_len = reader.u16()? as usize;
+        // Booleans carry only a name, numbers also carry an unit
+        let unit_len = if type_info & TYPE_INFO_BOOL != 0 { 0 } else { reader.u16()? as usize };
+        let name     = reader.text(name_len)?;
+        let _unit    = reader.text(unit_len)?;
+        Some(name)
+    } else {
+        None
+    };
+
+    let raw   = reader.uint(width)?;
+    let value = if type_info & TYPE_INFO_BOOL != 0 {
+        Value::Bool(raw != 0)
+    } else if type_info & TYPE_INFO_UINT != 0 {
+        Value::Unsigned(raw)
+    } else if type_info & TYPE_INFO_SINT != 0 {
+        // Sign extension from the argument width
+        let shift = 64 - width * 8;
+        Value::Signed(((raw << shift) as i64) >> shift)
+    } else if type_info & TYPE_INFO_FLOA != 0 {
+        match width {
+            4 => Value::Float(f32::from_bits(raw as u32) as f64),
+            8 => Value::Float(f64::from_bits(raw)),
+            _ => return None
+        }
+    } else {
+        return None;
+    };
+
+    Some(Argument { name, value })
+}
+
+#[test]
+fn parses_verbose_log_message() {
+    let mut message = vec![
+        // Standard header: UEH | WEID | WTMS | VERSION1, counter 7, length
+        HTYP_UEH | HTYP_WEID | HTYP_WTMS | HTYP_VERSION1, 7, 0, 0,
+        b'E', b'C', b'U', b'1',
+        0, 0, 0, 42,
+        // Extended header: verbose log, level info, 2 arguments
+        MSIN_VERB | (4 << 4), 2, b'R', b'L', b'O', b'G', b'T', b'E', b'S', b'T',
+    ];
+    // "Hello\0" as UTF-8 string
+    message.extend_from_slice(&[0x00, 0x82, 0x00, 0x00, 6, 0]);
+    message.extend_from_slice(b"Hello\0");
+    // -2 as int16
+    message.extend_from_slice(&[0x22, 0x00, 0x00, 0x00, 0xfe, 0xff]);
+    let len = message.len();
+    message[3] = len as u8;
+
+    match parse_record(&message) {
+        Parsed::Complete(record, size) => {
+            assert_eq!(size, len);
+            assert_eq!(record.ecu_id, Some("ECU1".to_owned()));
+            assert_eq!(record.timestamp, Some(42));
+            assert_eq!(record.app_id, "RLOG");
+            assert_eq!(record.context_id, "TEST");
+            assert_eq!(record.level, Some(Level::Info));
+            assert_eq!(record.text(), "Hello -2");
+        },
+        other => panic!("unexpected parse result: {:?}", other)
+    }
+}
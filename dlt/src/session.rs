@@ -0,0 +1,210 @@
+//! Markers and sessions: delimiting parts of a long trace, one test case for example.
+//!
+//! A `Session` logs a begin message when created and an end message when dropped, both
+//! verbose with 3 arguments: `"session-begin"` or `"session-end"`, the session number(`u32`,
+//! increasing for the whole process) and the label. `split_sessions` finds them again in a
+//! `.dlt` file:
+//!
+//! ```no_run
+//! let _dlt = dlt::init("RLOG", "Rust logging").unwrap();
+//! let ctx  = dlt::Context::register("TEST", "Test runner").unwrap();
+//! {
+//!     let _session = dlt::Session::begin(&ctx, "connects_to_server").unwrap();
+//!     ctx.log(dlt::Level::Info, "connecting").unwrap();
+//! }
+//! for session in dlt::split_sessions("/tmp/trace.dlt").unwrap() {
+//!     println!("{}: {} messages", session.label, session.records.len());
+//! }
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use ffi;
+use protocol::{ parse_stored_record, Parsed, Record, StoredRecord, Value };
+use { check, Context, Level, Result };
+
+const BEGIN: &str = "session-begin";
+const END: &str   = "session-end";
+
+/// Level of the begin and end messages
+const MARKER_LEVEL: Level = Level::Info;
+
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Asks the daemon to log a marker message, `dlt_log_marker`
+pub fn marker() -> Result<()> {
+    check(unsafe { ffi::dlt_log_marker() })
+}
+
+/// A part of the trace, ended when dropped
+#[must_use = "the session ends when dropped"]
+pub struct Session {
+    context: Context,
+    number: u32,
+    label: String
+}
+
+impl Session {
+    /// Logs the begin message of a new session on `context`, and a daemon marker when
+    /// a daemon is reachable
+    pub fn begin(context: &Context, label: &str) -> Result<Session> {
+        let number = SESSIONS.fetch_add(1, Ordering::Relaxed) as u32 + 1;
+
+        // Built once begun, a session that failed to begin must not end
+        let _ = marker();
+        log(context, BEGIN, number, label)?;
+        Ok(Session { context: context.clone(), number, label: label.to_owned() })
+    }
+
+    /// Number of the session, unique in the process
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Label given to `begin`, logged with the begin and end messages
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = log(&self.context, END, self.number, &self.label);
+        let _ = marker();
+    }
+}
+
+/// Logs the begin or end message of a session
+fn log(context: &Context, what: &str, number: u32, label: &str) -> Result<()> {
+    match context.message(MARKER_LEVEL) {
+        Some(mut message) => message.string(what).u32(number).string(label).send(),
+        None              => Ok(())
+    }
+}
+
+/// The messages of one session found in a trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionLog {
+    pub app_id: String,
+    pub number: u32,
+    pub label: String,
+    /// Messages logged between the begin and end messages, excluded
+    pub records: Vec<StoredRecord>,
+    /// `false` if the trace ends before the end message
+    pub complete: bool
+}
+
+/// Begin or end message: application, kind, number and label
+fn boundary(record: &Record) -> Option<(&str, u32, &str)> {
+    if record.level != Some(MARKER_LEVEL) || record.arguments.len() != 3 {
+        return None;
+    }
+
+    match (&record.arguments[0].value, &record.arguments[1].value, &record.arguments[2].value) {
+        (Value::String(kind), &Value::Unsigned(number), Value::String(label)) if kind == BEGIN || kind == END => {
+            Some((kind.as_str(), number as u32, label.as_str()))
+        },
+        _ => None
+    }
+}
+
+/// Splits the messages of a `.dlt` file into the sessions they were logged in, see
+/// `split_session_records`
+pub fn split_sessions<P: AsRef<Path>>(path: P) -> Result<Vec<SessionLog>> {
+    let trace = fs::read(path)?;
+    Ok(split_session_records(&trace))
+}
+
+/// Splits the messages of a trace(storage headers included) into sessions.
+///
+/// A message belongs to every session open at the time, messages outside any session are
+/// left out. The sessions are in the order they began in.
+pub fn split_session_records(trace: &[u8]) -> Vec<SessionLog> {
+    let mut sessions: Vec<SessionLog> = Vec::new();
+    let mut pos = 0;
+    while pos < trace.len() {
+        let stored = match parse_stored_record(&trace[pos..]) {
+            Parsed::Complete(stored, size) => {
+                pos += size;
+                stored
+            },
+            Parsed::Garbage(size) => {
+                pos += size;
+                continue;
+            },
+            Parsed::Incomplete => break
+        };
+
+        match boundary(&stored.record) {
+            Some((BEGIN, number, label)) => sessions.push(SessionLog {
+                app_id: stored.record.app_id.clone(),
+                number,
+                label: label.to_owned(),
+                records: Vec::new(),
+                complete: false
+            }),
+            Some((_, number, _)) => {
+                let app_id = &stored.record.app_id;
+                if let Some(session) = sessions.iter_mut().find(|s| !s.complete && s.number == number && s.app_id == *app_id) {
+                    session.complete = true;
+                }
+            },
+            None => {
+                for session in sessions.iter_mut().filter(|session| !session.complete) {
+                    session.records.push(stored.clone());
+                }
+            }
+        }
+    }
+
+    sessions
+}
+
+#[test]
+fn trace_is_split_on_session_boundaries() {
+    use protocol::{ storage_header, HTYP_UEH, HTYP_VERSION1, MSIN_VERB, TYPE_INFO_SCOD_UTF8, TYPE_INFO_STRG,
+                    TYPE_INFO_TYLE_32BIT, TYPE_INFO_UINT };
+    use std::time::UNIX_EPOCH;
+
+    fn string(payload: &mut Vec<u8>, text: &str) {
+        payload.extend_from_slice(&(TYPE_INFO_STRG | TYPE_INFO_SCOD_UTF8).to_le_bytes());
+        payload.extend_from_slice(&(text.len() as u16 + 1).to_le_bytes());
+        payload.extend_from_slice(text.as_bytes());
+        payload.push(0);
+    }
+
+    fn stored(arguments: &[&str], number: Option<u32>) -> Vec<u8> {
+        let mut payload = Vec::new();
+        string(&mut payload, arguments[0]);
+        if let Some(number) = number {
+            payload.extend_from_slice(&(TYPE_INFO_UINT | TYPE_INFO_TYLE_32BIT).to_le_bytes());
+            payload.extend_from_slice(&number.to_le_bytes());
+            string(&mut payload, arguments[1]);
+        }
+
+        let len = 4 + 10 + payload.len();
+        let mut message = storage_header(UNIX_EPOCH, b"ECU1").to_vec();
+        message.extend_from_slice(&[HTYP_UEH | HTYP_VERSION1, 0, 0, len as u8]);
+        message.extend_from_slice(&[MSIN_VERB | (4 << 4), if number.is_some() { 3 } else { 1 }]);
+        message.extend_from_slice(b"RLOGTEST");
+        message.extend_from_slice(&payload);
+        message
+    }
+
+    let mut trace = stored(&["before"], None);
+    trace.extend(stored(&[BEGIN, "first"], Some(1)));
+    trace.extend(stored(&["inside"], None));
+    trace.extend(stored(&[END, "first"], Some(1)));
+    trace.extend(stored(&["between"], None));
+    trace.extend(stored(&[BEGIN, "second"], Some(2)));
+    trace.extend(stored(&["unfinished"], None));
+
+    let sessions = split_session_records(&trace);
+    assert_eq!(sessions.len(), 2);
+    assert_eq!((sessions[0].number, sessions[0].label.as_str(), sessions[0].complete), (1, "first", true));
+    assert_eq!(sessions[0].records.iter().map(|stored| stored.record.text()).collect::<Vec<_>>(), vec!["inside"]);
+    assert_eq!((sessions[1].label.as_str(), sessions[1].complete), ("second", false));
+    assert_eq!(sessions[1].records[0].record.text(), "unfinished");
+}
@@ -0,0 +1,207 @@
+//! Shared memory transport of libdlt(`dlt_shm_*`), for evaluating SHM mode.
+//!
+//! A `ShmServer`(the daemon side) creates the segment, `ShmClient`s attach to it with the same
+//! key and push messages, which the server pulls in order. Keep away from `DEFAULT_SHM_KEY`,
+//! the segment of the daemon. libdlt guards every segment with the same semaphore
+//! (`DLT_SHM_SEM`), so only one server can exist at a time, and none next to a daemon built
+//! with SHM support:
+//!
+//! ```no_run
+//! const KEY: i32 = 0x444c_7273;
+//!
+//! let mut server = dlt::ShmServer::create(KEY, dlt::DEFAULT_SHM_SIZE).unwrap();
+//! let mut client = dlt::ShmClient::attach(KEY).unwrap();
+//!
+//! client.push(&[b"header", b"payload"]).unwrap();
+//! for message in server.messages() {
+//!     println!("{} bytes", message.len());
+//! }
+//! ```
+
+use std::mem;
+use std::ptr;
+
+use libc::{ c_int, c_uint };
+#[cfg(test)]
+use libc::{ key_t, semget };
+
+use ffi::{ self, DltReturnValue, DltShm };
+use shm_ring::ShmStats;
+use { check, Error, Result };
+
+/// Key of the segment libdlt, and the daemon, use by default
+pub const DEFAULT_SHM_KEY: i32 = ffi::DLT_SHM_KEY as i32;
+/// Size of the segment libdlt creates by default, in bytes
+pub const DEFAULT_SHM_SIZE: usize = ffi::DLT_SHM_SIZE as usize;
+
+fn stats(shm: &mut DltShm, recoveries: u32) -> ShmStats {
+    unsafe {
+        ShmStats {
+            total_size: ffi::dlt_shm_get_total_size(shm).max(0) as usize,
+            used_size: ffi::dlt_shm_get_used_size(shm).max(0) as usize,
+            message_count: ffi::dlt_shm_get_message_count(shm).max(0) as usize,
+            recoveries
+        }
+    }
+}
+
+/// Creator and reader of the segment
+pub struct ShmServer {
+    shm: DltShm,
+    /// Large enough for any message of the segment
+    scratch: Vec<u8>,
+    recoveries: u32
+}
+
+// The segment is shared between processes anyway, libdlt locks it for every operation
+unsafe impl Send for ShmServer {}
+
+impl ShmServer {
+    /// Creates the segment `key` with a data area of at least `size` bytes
+    ///
+    /// ### Returns
+    /// `Error::Dlt` when the segment or the semaphore of libdlt already exist
+    pub fn create(key: i32, size: usize) -> Result<ShmServer> {
+        if size > c_int::MAX as usize {
+            return Err(Error::InvalidConfig(format!("shared memory size of {} bytes is too large", size)));
+        }
+
+        let mut shm: DltShm = unsafe { mem::zeroed() };
+        check(unsafe { ffi::dlt_shm_init_server(&mut shm, key, size as c_int) })?;
+
+        let total_size = unsafe { ffi::dlt_shm_get_total_size(&mut shm) }.max(0) as usize;
+        Ok(ShmServer { shm, scratch: vec![0; total_size.max(size)], recoveries: 0 })
+    }
+
+    /// Removes the oldest message from the segment, `None` when there is none.
+    ///
+    /// When messages are left that can't be read, the segment is recovered(or reset) and
+    /// read once more.
+    pub fn pull(&mut self) -> Option<Vec<u8>> {
+        for _ in 0..2 {
+            let size = unsafe { ffi::dlt_shm_pull(&mut self.shm, self.scratch.as_mut_ptr(), self.scratch.len() as c_int) };
+            if size > 0 {
+                return Some(self.scratch[..size as usize].to_vec());
+            }
+
+            if unsafe { ffi::dlt_shm_get_message_count(&mut self.shm) } <= 0 {
+                return None;
+            }
+            self.recover();
+        }
+
+        None
+    }
+
+    /// Pulls the messages until the segment is empty
+    pub fn messages(&mut self) -> ShmMessages<'_> {
+        ShmMessages { server: self }
+    }
+
+    /// Size, use and message count of the segment, with the number of times it was recovered
+    pub fn stats(&mut self) -> ShmStats {
+        stats(&mut self.shm, self.recoveries)
+    }
+
+    fn recover(&mut self) {
+        self.recoveries += 1;
+        unsafe {
+            if ffi::dlt_shm_recover(&mut self.shm) < 0 {
+                ffi::dlt_shm_reset(&mut self.shm);
+            }
+        }
+    }
+}
+
+impl Drop for ShmServer {
+    fn drop(&mut self) {
+        unsafe { ffi::dlt_shm_free_server(&mut self.shm); }
+    }
+}
+
+/// Messages pulled by `ShmServer::messages`
+pub struct ShmMessages<'a> {
+    server: &'a mut ShmServer
+}
+
+impl<'a> Iterator for ShmMessages<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.server.pull()
+    }
+}
+
+/// Writer attached to the segment of a server
+pub struct ShmClient {
+    shm: DltShm
+}
+
+unsafe impl Send for ShmClient {}
+
+impl ShmClient {
+    /// Attaches to the segment `key`, which the server must have created
+    pub fn attach(key: i32) -> Result<ShmClient> {
+        let mut shm: DltShm = unsafe { mem::zeroed() };
+        check(unsafe { ffi::dlt_shm_init_client(&mut shm, key) })?;
+
+        Ok(ShmClient { shm })
+    }
+
+    /// Pushes the concatenation of up to three slices as one message
+    ///
+    /// ### Returns
+    /// `Error::Dlt` with the code of libdlt, `DLT_RETURN_ERROR` when the segment is full, or
+    /// `DLT_RETURN_WRONG_PARAMETER` when more than three slices are given
+    pub fn push(&mut self, parts: &[&[u8]]) -> Result<()> {
+        if parts.len() > 3 || parts.iter().any(|part| part.len() > c_int::MAX as usize) {
+            return Err(Error::Dlt(DltReturnValue::DLT_RETURN_WRONG_PARAMETER as i32));
+        }
+
+        let mut slices = [(ptr::null(), 0); 3];
+        for (slice, part) in slices.iter_mut().zip(parts) {
+            *slice = (part.as_ptr(), part.len() as c_uint);
+        }
+
+        let ret = unsafe {
+            ffi::dlt_shm_push(&mut self.shm, slices[0].0, slices[0].1, slices[1].0, slices[1].1, slices[2].0, slices[2].1)
+        };
+        if ret < 0 {
+            return Err(Error::Dlt(ret));
+        }
+
+        Ok(())
+    }
+
+    /// Size, use and message count of the segment as the client sees it, `recoveries` is
+    /// always 0 as only the server recovers
+    pub fn stats(&mut self) -> ShmStats {
+        stats(&mut self.shm, 0)
+    }
+}
+
+impl Drop for ShmClient {
+    fn drop(&mut self) {
+        unsafe { ffi::dlt_shm_free_client(&mut self.shm); }
+    }
+}
+
+#[test]
+fn pushed_messages_are_pulled_in_order() {
+    // The semaphore belongs to a daemon built with SHM support, freeing the server would remove it
+    assert!(unsafe { semget(ffi::DLT_SHM_SEM as key_t, 0, 0) } < 0,
+            "DLT_SHM_SEM is in use, stop the DLT daemon built with SHM support before running this test");
+
+    let key = 0x444c_0000 | (::std::process::id() as i32 & 0xffff);
+    let mut server = ShmServer::create(key, 4096).unwrap();
+    let mut client = ShmClient::attach(key).unwrap();
+
+    client.push(&[b"first"]).unwrap();
+    client.push(&[b"sec", b"on", b"d"]).unwrap();
+    assert!(client.push(&[b"a", b"b", b"c", b"d"]).is_err());
+    assert_eq!(server.stats().message_count, 2);
+
+    let messages: Vec<Vec<u8>> = server.messages().collect();
+    assert_eq!(messages, vec![b"first".to_vec(), b"second".to_vec()]);
+    assert_eq!((server.stats().message_count, server.stats().recoveries), (0, 0));
+}
@@ -0,0 +1,481 @@
+//! Shared memory ring buffer in the layout of libdlt's, written without its semaphore.
+//!
+//! The segment starts with a `DltBufferHead`(write offset, read offset and message count,
+//! `c_int`s) followed by the data area, in which every message is a `DltShmBlockHead`
+//! (`"SHM\0"`, status, size) and the message itself, wrapping at the end of the area.
+//!
+//! Writers reserve their block by moving the write offset with a compare-and-swap, copy the
+//! message, and publish it by setting the status to `2`, so they never wait for each other.
+//! The swap covers both offsets: when the reader moved in between, the free space is checked
+//! again. A single reader takes the blocks in order and zeroes them before handing the space
+//! back.
+//! The segment is a memfd or POSIX shared memory object that other processes map through its
+//! file descriptor or name:
+//!
+//! ```no_run
+//! let ring = dlt::ShmRing::create(64 * 1024).unwrap();
+//! ring.push(&[b"header", b"payload"]).unwrap();
+//!
+//! let mut reader = ring;
+//! while let Some(message) = reader.pull() {
+//!     println!("{} bytes", message.len());
+//! }
+//! ```
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd };
+use std::ptr;
+use std::sync::atomic::{ AtomicI32, AtomicU64, AtomicU8, Ordering };
+
+use libc::{ self, c_int };
+
+use ffi::DltReturnValue;
+use { Error, Result };
+
+/// Size of `DltBufferHead`: write offset, read offset and message count
+const BUFFER_HEAD_SIZE: usize = 12;
+const COUNT: usize = 8;
+
+/// Size of `DltShmBlockHead`, padding of the status included
+const BLOCK_HEAD_SIZE: usize = 12;
+const STATUS: usize = 4;
+const SIZE: usize   = 8;
+
+/// `DLT_SHM_HEAD`
+const HEAD: &[u8; 4] = b"SHM\0";
+/// Status of a block completely written, what libdlt checks before reading it
+const STATUS_READY: u8 = 2;
+
+/// Occupation of the segment
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShmStats {
+    /// Size of the data area
+    pub total_size: usize,
+    /// Bytes taken by the messages and their block headers
+    pub used_size: usize,
+    pub message_count: usize,
+    /// Times the reader found the segment corrupted and recovered, `0` for writers
+    pub recoveries: u32
+}
+
+/// Lock-free ring buffer over a shared memory segment
+pub struct ShmRing {
+    file: File,
+    base: *mut u8,
+    /// Size of the mapping: buffer head and data area
+    len: usize,
+    recoveries: u32
+}
+
+// The segment is shared between processes anyway, every access to it goes through atomics
+unsafe impl Send for ShmRing {}
+unsafe impl Sync for ShmRing {}
+
+impl ShmRing {
+    /// Creates an anonymous segment(memfd) with a data area of `size` bytes, shared by handing
+    /// out its file descriptor
+    pub fn create(size: usize) -> Result<ShmRing> {
+        let len = segment_len(size)?;
+
+        let fd = unsafe { libc::memfd_create(b"dlt-shm\0".as_ptr() as *const _, libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(len as u64)?;
+        ShmRing::map(file)
+    }
+
+    /// Creates the POSIX shared memory object `name`(`/dlt-shm` for example) with a data area
+    /// of `size` bytes, failing if it exists
+    pub fn create_posix(name: &str, size: usize) -> Result<ShmRing> {
+        let len = segment_len(size)?;
+
+        let file = shm_open(name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL)?;
+        file.set_len(len as u64)?;
+        ShmRing::map(file)
+    }
+
+    /// Maps the POSIX shared memory object `name` created by `create_posix`
+    pub fn open_posix(name: &str) -> Result<ShmRing> {
+        ShmRing::map(shm_open(name, libc::O_RDWR)?)
+    }
+
+    /// Removes the POSIX shared memory object `name`, the processes mapping it keep it until
+    /// they drop their `ShmRing`
+    pub fn unlink_posix(name: &str) -> Result<()> {
+        let c_name = CString::new(name)?;
+        if unsafe { libc::shm_unlink(c_name.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    /// Maps a segment received from another process, a memfd or shared memory object
+    pub fn from_file(file: File) -> Result<ShmRing> {
+        ShmRing::map(file)
+    }
+
+    fn map(file: File) -> Result<ShmRing> {
+        let len = file.metadata()?.len() as usize;
+        if segment_len(len.saturating_sub(BUFFER_HEAD_SIZE)).is_err() {
+            return Err(Error::InvalidConfig(format!("shared memory segment of {} bytes can't hold a ring buffer", len)));
+        }
+
+        let base = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let ring = ShmRing { file, base: base as *mut u8, len, recoveries: 0 };
+        if ring.offsets(ring.head().load(Ordering::Acquire)).is_none() {
+            return Err(Error::InvalidConfig("shared memory segment has offsets outside its data area".to_owned()));
+        }
+
+        Ok(ring)
+    }
+
+    /// Pushes the concatenation of up to three slices as one message, from any thread or
+    /// process
+    ///
+    /// ### Returns
+    /// `Error::Dlt` when the segment is full or corrupted, or more than three slices are given
+    pub fn push(&self, parts: &[&[u8]]) -> Result<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if parts.len() > 3 || len > c_int::MAX as usize {
+            return Err(Error::Dlt(DltReturnValue::DLT_RETURN_WRONG_PARAMETER as i32));
+        }
+
+        let size = self.size();
+        let needed = BLOCK_HEAD_SIZE + len;
+        let head = self.head();
+        // The reader zeroes the blocks before moving the read offset past them
+        let mut current = head.load(Ordering::Acquire);
+        let start = loop {
+            let (read, write) = match self.offsets(current) {
+                Some(offsets) => offsets,
+                None          => return Err(Error::Dlt(DltReturnValue::DLT_RETURN_ERROR as i32))
+            };
+            if needed > size - 1 - used(size, read, write) {
+                return Err(Error::Dlt(DltReturnValue::DLT_RETURN_BUFFER_FULL as i32));
+            }
+
+            match head.compare_exchange_weak(current, pack(read, (write + needed) % size), Ordering::Acquire, Ordering::Acquire) {
+                Ok(_)       => break write,
+                Err(actual) => current = actual
+            }
+        };
+
+        unsafe {
+            self.copy_in(start, HEAD);
+            self.copy_in(start + SIZE, &(len as c_int).to_ne_bytes());
+            let mut offset = start + BLOCK_HEAD_SIZE;
+            for part in parts {
+                self.copy_in(offset, part);
+                offset += part.len();
+            }
+        }
+
+        self.counter(COUNT).fetch_add(1, Ordering::Relaxed);
+        self.status(start).store(STATUS_READY, Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the oldest message from the segment, `None` when there is none or the oldest
+    /// one is still being written.
+    ///
+    /// A block that isn't a `DltShmBlockHead`, or offsets outside the data area, mean the
+    /// segment is corrupted: every message in it is dropped. Blocks being written are dropped
+    /// too and their writers may still publish them in the space handed back, so recovering
+    /// is only reliable while no writer is pushing.
+    pub fn pull(&mut self) -> Option<Vec<u8>> {
+        let size = self.size();
+        let (read, write) = match self.offsets(self.head().load(Ordering::Acquire)) {
+            Some(offsets) => offsets,
+            None          => {
+                self.recover();
+                return None;
+            }
+        };
+        if read == write || self.status(read).load(Ordering::Acquire) != STATUS_READY {
+            return None;
+        }
+
+        let mut head = [0; 4];
+        let mut len = [0; 4];
+        unsafe {
+            self.copy_out(read, &mut head);
+            self.copy_out(read + SIZE, &mut len);
+        }
+
+        let len = c_int::from_ne_bytes(len);
+        if head != *HEAD || len < 0 || BLOCK_HEAD_SIZE + len as usize > used(size, read, write) {
+            self.recover();
+            return None;
+        }
+
+        let mut message = vec![0; len as usize];
+        let needed = BLOCK_HEAD_SIZE + message.len();
+        unsafe {
+            self.copy_out(read + BLOCK_HEAD_SIZE, &mut message);
+            self.zero(read, needed);
+        }
+
+        self.counter(COUNT).fetch_sub(1, Ordering::Relaxed);
+        self.move_read((read + needed) % size);
+        Some(message)
+    }
+
+    /// Occupation of the segment, empty while the offsets are corrupted
+    pub fn stats(&self) -> ShmStats {
+        let (read, write) = self.offsets(self.head().load(Ordering::Relaxed)).unwrap_or((0, 0));
+
+        ShmStats {
+            total_size: self.size(),
+            used_size: used(self.size(), read, write),
+            message_count: self.counter(COUNT).load(Ordering::Relaxed).max(0) as usize,
+            recoveries: self.recoveries
+        }
+    }
+
+    /// Drops everything up to the write offset, what `dlt_shm_reset` does, or the whole data
+    /// area when the offsets are corrupted
+    fn recover(&mut self) {
+        self.recoveries += 1;
+
+        match self.offsets(self.head().load(Ordering::Acquire)) {
+            Some((read, write)) => {
+                unsafe { self.zero(read, used(self.size(), read, write)); }
+                self.move_read(write);
+            },
+            None => {
+                unsafe { self.zero(0, self.size()); }
+                self.head().store(pack(0, 0), Ordering::Release);
+            }
+        }
+        self.counter(COUNT).store(0, Ordering::Relaxed);
+    }
+
+    /// Hands the space before `read` back to the writers
+    fn move_read(&self, read: usize) {
+        let head = self.head();
+        let mut current = head.load(Ordering::Relaxed);
+        loop {
+            let mut bytes = current.to_ne_bytes();
+            bytes[4..].copy_from_slice(&(read as c_int).to_ne_bytes());
+
+            match head.compare_exchange_weak(current, u64::from_ne_bytes(bytes), Ordering::Release, Ordering::Relaxed) {
+                Ok(_)       => break,
+                Err(actual) => current = actual
+            }
+        }
+    }
+
+    /// Write and read offsets of `DltBufferHead`, swapped together
+    fn head(&self) -> &AtomicU64 {
+        unsafe { &*(self.base as *const AtomicU64) }
+    }
+
+    /// Read and write offsets packed in `word`, `None` when one is outside the data area
+    fn offsets(&self, word: u64) -> Option<(usize, usize)> {
+        let bytes = word.to_ne_bytes();
+        let write = c_int::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let read = c_int::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+
+        let size = self.size() as c_int;
+        if (0..size).contains(&read) && (0..size).contains(&write) {
+            Some((read as usize, write as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Size of the data area
+    fn size(&self) -> usize {
+        self.len - BUFFER_HEAD_SIZE
+    }
+
+    fn counter(&self, offset: usize) -> &AtomicI32 {
+        unsafe { &*(self.base.add(offset) as *const AtomicI32) }
+    }
+
+    /// Status byte of the block starting at `offset` of the data area
+    fn status(&self, offset: usize) -> &AtomicU8 {
+        unsafe { &*(self.data((offset + STATUS) % self.size()) as *const AtomicU8) }
+    }
+
+    fn data(&self, offset: usize) -> *mut u8 {
+        unsafe { self.base.add(BUFFER_HEAD_SIZE + offset) }
+    }
+
+    /// Calls `f` with the one or two parts of `len` bytes from `offset` of the data area,
+    /// split where it wraps
+    fn split<F: FnMut(*mut u8, usize, usize)>(&self, offset: usize, len: usize, mut f: F) {
+        let offset = offset % self.size();
+        let first = len.min(self.size() - offset);
+        f(self.data(offset), 0, first);
+        if first < len {
+            f(self.data(0), first, len - first);
+        }
+    }
+
+    unsafe fn copy_in(&self, offset: usize, bytes: &[u8]) {
+        self.split(offset, bytes.len(), |dst, from, len| ptr::copy_nonoverlapping(bytes.as_ptr().add(from), dst, len));
+    }
+
+    unsafe fn copy_out(&self, offset: usize, bytes: &mut [u8]) {
+        let out = bytes.as_mut_ptr();
+        self.split(offset, bytes.len(), |src, from, len| ptr::copy_nonoverlapping(src, out.add(from), len));
+    }
+
+    unsafe fn zero(&self, offset: usize, len: usize) {
+        self.split(offset, len, |dst, _, len| ptr::write_bytes(dst, 0, len));
+    }
+}
+
+impl AsRawFd for ShmRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for ShmRing {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut _, self.len); }
+    }
+}
+
+/// Size of the segment for a data area of `size` bytes, which must hold a message
+fn segment_len(size: usize) -> Result<usize> {
+    if size <= BLOCK_HEAD_SIZE || size > c_int::MAX as usize {
+        return Err(Error::InvalidConfig(format!("shared memory size of {} bytes is out of range", size)));
+    }
+
+    Ok(BUFFER_HEAD_SIZE + size)
+}
+
+/// `DltBufferHead` offsets as swapped by `ShmRing::head`
+fn pack(read: usize, write: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&(write as c_int).to_ne_bytes());
+    bytes[4..].copy_from_slice(&(read as c_int).to_ne_bytes());
+    u64::from_ne_bytes(bytes)
+}
+
+/// Bytes between the read and write offsets. One byte is always left free, so equal offsets
+/// mean an empty ring.
+fn used(size: usize, read: usize, write: usize) -> usize {
+    (write + size - read) % size
+}
+
+fn shm_open(name: &str, flags: c_int) -> Result<File> {
+    let c_name = CString::new(name)?;
+    let fd = unsafe { libc::shm_open(c_name.as_ptr(), flags, 0o600) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+#[test]
+fn concurrent_writers_keep_their_order() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let ring = Arc::new(ShmRing::create(256).unwrap());
+    ring.push(&[b"SH", b"M"]).unwrap();
+
+    // The block is what libdlt expects: "SHM\0", status 2, padding and the native size
+    let mut block = [0; BLOCK_HEAD_SIZE + 3];
+    unsafe { ring.copy_out(0, &mut block); }
+    assert_eq!(&block, b"SHM\0\x02\0\0\0\x03\0\0\0SHM");
+    assert_eq!(ring.push(&[&[0; 256]]).unwrap_err().to_string(), "DLT returned -4(buffer full)");
+
+    let mut reader = ShmRing::from_file(ring.file.try_clone().unwrap()).unwrap();
+    assert_eq!(reader.pull().unwrap(), b"SHM");
+
+    let corrupted = ShmRing::create(256).unwrap();
+    corrupted.head().store(pack(0, 300), Ordering::Relaxed);
+    assert!(ShmRing::from_file(corrupted.file.try_clone().unwrap()).is_err());
+    assert!(corrupted.push(&[b"SHM"]).is_err());
+
+    let writers: Vec<_> = (0..4u8).map(|writer| {
+        let ring = ring.clone();
+        thread::spawn(move || {
+            for number in 0..200u8 {
+                while ring.push(&[&[writer], &[number; 9]]).is_err() {
+                    thread::yield_now();
+                }
+            }
+        })
+    }).collect();
+
+    let mut next = [0u8; 4];
+    while next.iter().any(|&number| number < 200) {
+        match reader.pull() {
+            Some(message) => {
+                let writer = message[0] as usize;
+                assert_eq!(&message[1..], &[next[writer]; 9]);
+                next[writer] += 1;
+            },
+            None => thread::yield_now()
+        }
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let stats = reader.stats();
+    assert_eq!((stats.total_size, stats.used_size, stats.message_count, stats.recoveries), (256, 0, 0, 0));
+}
+
+#[test]
+fn tiny_ring_survives_many_writers() {
+    use std::sync::Arc;
+    use std::thread;
+
+    const WRITERS: usize = 16;
+    const MESSAGES: usize = 2000;
+
+    // Room for 2 to 3 messages: the write offset laps the ring over and over
+    let ring = Arc::new(ShmRing::create(64).unwrap());
+    let mut reader = ShmRing::from_file(ring.file.try_clone().unwrap()).unwrap();
+
+    let writers: Vec<_> = (0..WRITERS).map(|writer| {
+        let ring = ring.clone();
+        thread::spawn(move || {
+            for number in 0..MESSAGES {
+                let payload = vec![number as u8; 1 + number % 13];
+                while ring.push(&[&[writer as u8], &(number as u16).to_le_bytes(), &payload]).is_err() {
+                    thread::yield_now();
+                }
+            }
+        })
+    }).collect();
+
+    let mut next = [0; WRITERS];
+    while next.iter().any(|&number| number < MESSAGES) {
+        match reader.pull() {
+            Some(message) => {
+                let writer = message[0] as usize;
+                let number = u16::from_le_bytes([message[1], message[2]]) as usize;
+                assert_eq!(number, next[writer]);
+                assert_eq!(&message[3..], &vec![number as u8; 1 + number % 13][..]);
+                next[writer] += 1;
+            },
+            None => thread::yield_now()
+        }
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(reader.stats().recoveries, 0);
+}
@@ -0,0 +1,260 @@
+//! Making sure the last messages of a process reach the daemon.
+//!
+//! Messages still in the startup buffer when the process ends are lost, and those are
+//! usually the ones explaining why it ended. `init` returns a `FlushGuard` that empties
+//! the buffer when dropped and when a thread panics.
+
+use std::io::{ self, Write };
+use std::panic;
+use std::sync::{ Arc, Mutex, MutexGuard, Once };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Duration;
+
+use buffer::{ buffer_stats, flush_buffer, set_resend_timeout };
+use config::Config;
+use { duration_ms, Application, Error, Result };
+
+type Report = Arc<dyn Fn(&FlushReport) + Send + Sync>;
+
+/// Deadline used by `init`, 1 second like `DLT_USER_ATEXIT_RESEND_BUFFER_EXIT_TIMEOUT`
+pub const DEFAULT_FLUSH_DEADLINE: Duration = Duration::from_secs(1);
+
+/// Deadline of the flush done by the panic hook, in milliseconds
+static PANIC_DEADLINE_MS: AtomicUsize = AtomicUsize::new(1000);
+static PANIC_HOOK: Once = Once::new();
+/// Report of the guard alive, used by the panic hook
+static PANIC_REPORT: Mutex<Option<Report>> = Mutex::new(None);
+
+/// Outcome of a flush
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FlushReport {
+    /// Messages still buffered when the deadline expired, lost if the process ends now
+    pub pending: usize,
+    /// Size of the pending messages, in bytes
+    pub pending_bytes: usize,
+    /// Messages lost during the flush because the buffer was full
+    pub dropped: u32,
+    /// Messages lost because the buffer was full since the start of the process, `dropped`
+    /// included
+    pub dropped_since_start: u32
+}
+
+impl FlushReport {
+    /// Nothing was lost during the flush and nothing is left in the buffer
+    pub fn is_complete(&self) -> bool {
+        self.pending == 0 && self.dropped == 0
+    }
+}
+
+/// Flushes the startup buffer for at most `deadline`
+///
+/// ### Returns
+/// An error when the state of the buffer couldn't be read, whether it was emptied is unknown
+pub fn flush(deadline: Duration) -> Result<FlushReport> {
+    let before = buffer_stats()?;
+    let after  = flush_buffer(deadline).or_else(|_| buffer_stats())?;
+
+    Ok(FlushReport {
+        pending: after.messages,
+        pending_bytes: after.used,
+        dropped: after.overflow_count.wrapping_sub(before.overflow_count),
+        dropped_since_start: after.overflow_count
+    })
+}
+
+fn report_to_stderr(report: &FlushReport) {
+    if !report.is_complete() {
+        let _ = writeln!(io::stderr(), "dlt: {} messages still buffered, {} lost while flushing at shutdown",
+                         report.pending, report.dropped);
+    }
+}
+
+fn flush_failed(err: &Error) {
+    let _ = writeln!(io::stderr(), "dlt: couldn't flush the buffered messages at shutdown: {}", err);
+}
+
+/// Registers the application and returns the guard flushing its messages on shutdown.
+///
+/// A panic hook is installed too(once per process), it flushes the buffer after running
+/// the previously installed hook.
+pub fn init(app_id: &str, description: &str) -> Result<FlushGuard> {
+    let application = Application::register(app_id, description)?;
+    Ok(new_guard(application, DEFAULT_FLUSH_DEADLINE))
+}
+
+/// Like `init`, with the options of `config` applied once the application is registered.
+///
+/// The resend timeout of `config` is the deadline of the returned guard.
+pub fn init_with_config(app_id: &str, description: &str, config: &Config) -> Result<FlushGuard> {
+    config.validate()?;
+    let application = Application::register(app_id, description)?;
+    config.apply()?;
+
+    Ok(new_guard(application, config.resend_timeout()))
+}
+
+fn new_guard(application: Application, deadline: Duration) -> FlushGuard {
+    let guard = FlushGuard {
+        application: Some(application),
+        deadline,
+        report: Arc::new(report_to_stderr)
+    };
+    guard.apply_deadline();
+    *panic_report() = Some(guard.report.clone());
+
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            previous(info);
+
+            let deadline = PANIC_DEADLINE_MS.load(Ordering::Relaxed);
+            let report   = panic_report().clone().unwrap_or_else(|| Arc::new(report_to_stderr));
+            match flush(Duration::from_millis(deadline as u64)) {
+                Ok(flushed) => report(&flushed),
+                Err(err)    => flush_failed(&err)
+            }
+        }));
+    });
+
+    guard
+}
+
+fn panic_report() -> MutexGuard<'static, Option<Report>> {
+    match PANIC_REPORT.lock() {
+        Ok(report)    => report,
+        Err(poisoned) => poisoned.into_inner()
+    }
+}
+
+/// Owns the registered application, flushes the buffered messages before unregistering it
+pub struct FlushGuard {
+    application: Option<Application>,
+    deadline: Duration,
+    report: Report
+}
+
+impl FlushGuard {
+    /// The registered application
+    pub fn application(&self) -> &Application {
+        self.application.as_ref().unwrap()
+    }
+
+    /// How long dropping the guard, or a panic, may wait for the daemon
+    pub fn deadline(mut self, deadline: Duration) -> FlushGuard {
+        self.deadline = deadline;
+        self.apply_deadline();
+        self
+    }
+
+    /// Called with the outcome of the flush done on drop or on panic, by default it complains
+    /// on stderr when messages were lost. A flush that failed is reported on stderr only.
+    pub fn on_report<F>(mut self, report: F) -> FlushGuard
+        where F: Fn(&FlushReport) + Send + Sync + 'static
+    {
+        self.report = Arc::new(report);
+        *panic_report() = Some(self.report.clone());
+        self
+    }
+
+    /// Flushes now, waiting at most for the guard's deadline
+    pub fn flush(&self) -> Result<FlushReport> {
+        flush(self.deadline)
+    }
+
+    fn apply_deadline(&self) {
+        PANIC_DEADLINE_MS.store(duration_ms(self.deadline), Ordering::Relaxed);
+
+        // The user library's own exit handler waits as long
+        set_resend_timeout(self.deadline);
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        match self.flush() {
+            Ok(report) => (self.report)(&report),
+            Err(err)   => flush_failed(&err)
+        }
+
+        panic_report().take();
+        self.application.take();
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn guard_flushes_on_panic_and_on_drop() {
+    use std::thread;
+    use buffer::buffer_stats;
+    use test_util::{ self, MockDaemon };
+    use { Context, Level };
+
+    // Logs until the FIFO of the paused mock is full and `extra` more messages were buffered
+    fn fill(ctx: &Context, tag: &str, extra: usize) -> String {
+        let mut sent  = 0;
+        let mut until = None;
+        loop {
+            let text = format!("{} {:06} {}", tag, sent, "-".repeat(200));
+            ctx.log(Level::Info, &text).unwrap();
+            sent += 1;
+            if until.is_none() && buffer_stats().unwrap().messages > 0 {
+                until = Some(sent + extra);
+            }
+            if until == Some(sent) {
+                return text;
+            }
+            assert!(sent < 100_000, "the FIFO of the mock never filled up");
+        }
+    }
+
+    // Reads the FIFO again in a moment, while the guard flushes
+    fn resume_soon(daemon: &'static MockDaemon) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            daemon.resume();
+        })
+    }
+
+    // `init` registers the application itself
+    let _exclusive = test_util::exclusive();
+    let daemon  = MockDaemon::global();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let guard = {
+        let reports = reports.clone();
+        init("RLOG", "Rust logging").unwrap().deadline(Duration::from_secs(5))
+                                    .on_report(move |report| reports.lock().unwrap().push(*report))
+    };
+    let ctx = test_util::register_and_wait("SHUT", "Shutdown");
+
+    // Nothing drains while the mock is paused, the report tells what is left
+    daemon.pause();
+    let last = fill(&ctx, "before panic", 5);
+    let pending = buffer_stats().unwrap().messages;
+    let report  = flush(Duration::from_millis(20)).unwrap();
+    assert!(pending > 5);
+    assert_eq!((report.pending, report.dropped), (pending, 0));
+    assert!(report.pending_bytes > 0 && !report.is_complete());
+
+    // The panic hook flushes after the default hook printed the panic
+    let resumed   = resume_soon(daemon);
+    let panicking = ctx.clone();
+    assert!(thread::spawn(move || {
+        panicking.log(Level::Fatal, "giving up").unwrap();
+        panic!("on purpose");
+    }).join().is_err());
+    resumed.join().unwrap();
+    assert_eq!(reports.lock().unwrap().last().map(|report| report.pending), Some(0));
+    daemon.assert_logged("RLOG", "SHUT", Level::Info, |record| record.text() == last, "");
+    daemon.assert_logged("RLOG", "SHUT", Level::Fatal, |record| record.text() == "giving up", "");
+
+    daemon.pause();
+    let last    = fill(&ctx, "before drop", 5);
+    let resumed = resume_soon(daemon);
+    drop(ctx);
+    drop(guard);
+    resumed.join().unwrap();
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 2);
+    assert!(reports.iter().all(FlushReport::is_complete));
+    daemon.assert_logged("RLOG", "SHUT", Level::Info, |record| record.text() == last, "");
+}
@@ -0,0 +1,399 @@
+//! In-process stand-in for the DLT daemon, for unit-testing code that logs through DLT.
+//!
+//! The mock creates its own FIFO directory, points the user library at it through
+//! `dltFifoBaseDir` and records everything the application sends:
+//!
+//! ```no_run
+//! #[macro_use] extern crate dlt;
+//!
+//! # fn main() {
+//! // Must run before the application is registered
+//! dlt::test_util::MockDaemon::global();
+//!
+//! let _app = dlt::Application::register("MOCK", "Mocked application").unwrap();
+//! let ctx  = dlt::Context::register("TEST", "Mocked context").unwrap();
+//! ctx.log(dlt::Level::Warn, "something odd").unwrap();
+//!
+//! assert_logged!("MOCK", "TEST", dlt::Level::Warn, contains "odd");
+//! # }
+//! ```
+
+use std::env;
+use std::ffi::CString;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ Read, Write };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::ptr;
+use std::sync::{ Arc, Condvar, Mutex };
+#[cfg(test)]
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use libc;
+
+use ffi;
+use protocol::{ self, Parsed, Record, UserMessage };
+use Level;
+#[cfg(test)]
+use { Application, Context };
+
+/// How long the assertions wait for a message to arrive
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref GLOBAL: MockDaemon = MockDaemon::start().expect("Failed to start the mock DLT daemon");
+}
+
+#[derive(Default)]
+struct Captured {
+    messages: Vec<UserMessage>,
+    /// The FIFO isn't read meanwhile, like with a busy daemon
+    paused: bool
+}
+
+fn mock_dir() -> PathBuf {
+    env::temp_dir().join(format!("dlt-rs-mock-{}", process::id()))
+}
+
+extern "C" fn remove_mock_dir() {
+    let _ = fs::remove_dir_all(mock_dir());
+}
+
+/// A fake DLT daemon reading the daemon FIFO on a background thread
+pub struct MockDaemon {
+    dir: PathBuf,
+    captured: Arc<(Mutex<Captured>, Condvar)>,
+    // Keeps the FIFO open for writing, so the reader never sees an end of file
+    _writer: File
+}
+
+impl MockDaemon {
+    /// The process-wide mock daemon, started on first use.
+    ///
+    /// The user library reads `dltFifoBaseDir` only once, when the application is registered,
+    /// so this has to be called before any DLT API.
+    pub fn global() -> &'static MockDaemon {
+        &GLOBAL
+    }
+
+    fn start() -> ::std::io::Result<MockDaemon> {
+        let dir = mock_dir();
+        fs::create_dir_all(dir.join("dltpipes"))?;
+        // `GLOBAL` is never dropped, the directory goes away when the process exits instead
+        unsafe { libc::atexit(remove_mock_dir); }
+
+        let fifo = dir.join("dlt");
+        let _ = fs::remove_file(&fifo);
+        let c_fifo = CString::new(fifo.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(c_fifo.as_ptr(), 0o666) } != 0 {
+            return Err(::std::io::Error::last_os_error());
+        }
+
+        // Opening the read end first, opening a FIFO for writing without a reader fails
+        let mut reader = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&fifo)?;
+        let writer     = OpenOptions::new().write(true).open(&fifo)?;
+        unsafe {
+            let flags = libc::fcntl(reader.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+
+        set_fifo_base_dir(&dir);
+
+        let captured = Arc::new((Mutex::new(Captured::default()), Condvar::new()));
+        let shared   = captured.clone();
+        thread::Builder::new().name("dlt-mock-daemon".to_owned()).spawn(move || {
+            let mut pending = Vec::new();
+            let mut chunk   = [0u8; 4096];
+            loop {
+                {
+                    let (lock, condvar) = &*shared;
+                    let mut captured = lock.lock().unwrap();
+                    while captured.paused {
+                        captured = condvar.wait(captured).unwrap();
+                    }
+                }
+
+                let read = match reader.read(&mut chunk) {
+                    Ok(0) | Err(_) => return,
+                    Ok(read)       => read
+                };
+                pending.extend_from_slice(&chunk[..read]);
+
+                let mut consumed = 0;
+                let mut messages = Vec::new();
+                loop {
+                    match protocol::parse_user_message(&pending[consumed..]) {
+                        Parsed::Complete(message, size) => {
+                            messages.push(message);
+                            consumed += size;
+                        },
+                        Parsed::Garbage(size) => consumed += size,
+                        Parsed::Incomplete    => break
+                    }
+                }
+                pending.drain(..consumed);
+
+                if !messages.is_empty() {
+                    let (lock, condvar) = &*shared;
+                    lock.lock().unwrap().messages.extend(messages);
+                    condvar.notify_all();
+                }
+            }
+        })?;
+
+        Ok(MockDaemon { dir, captured, _writer: writer })
+    }
+
+    /// Directory used as `dltFifoBaseDir`
+    pub fn fifo_base_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stops reading the FIFO, once it is full the user library buffers the messages.
+    ///
+    /// A read already waiting for data still completes.
+    pub fn pause(&self) {
+        self.captured.0.lock().unwrap().paused = true;
+    }
+
+    /// Reads the FIFO again
+    pub fn resume(&self) {
+        let (lock, condvar) = &*self.captured;
+        lock.lock().unwrap().paused = false;
+        condvar.notify_all();
+    }
+
+    /// Every message received so far, in order
+    pub fn messages(&self) -> Vec<UserMessage> {
+        self.captured.0.lock().unwrap().messages.clone()
+    }
+
+    /// The log messages received so far, in order
+    pub fn records(&self) -> Vec<Record> {
+        self.messages().into_iter().filter_map(|message| match message {
+            UserMessage::Log(record) => Some(record),
+            _                        => None
+        }).collect()
+    }
+
+    /// Waits up to `timeout` for a message matching `predicate`
+    pub fn wait_for<F>(&self, timeout: Duration, mut predicate: F) -> Option<UserMessage>
+        where F: FnMut(&UserMessage) -> bool
+    {
+        let deadline = Instant::now() + timeout;
+        let (lock, condvar) = &*self.captured;
+
+        let mut captured = lock.lock().unwrap();
+        loop {
+            if let Some(message) = captured.messages.iter().find(|message| predicate(message)) {
+                return Some(message.clone());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            captured = condvar.wait_timeout(captured, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Waits for a log message of `app_id`/`context_id` with `level` matching `predicate`
+    pub fn wait_for_log<F>(&self, app_id: &str, context_id: &str, level: Level, timeout: Duration,
+                           mut predicate: F) -> Option<Record>
+        where F: FnMut(&Record) -> bool
+    {
+        let message = self.wait_for(timeout, |message| match *message {
+            UserMessage::Log(ref record) => record.app_id == app_id
+                                            && record.context_id == context_id
+                                            && record.level == Some(level)
+                                            && predicate(record),
+            _ => false
+        });
+
+        match message {
+            Some(UserMessage::Log(record)) => Some(record),
+            _                              => None
+        }
+    }
+
+    /// Waits for the registration of `context_id` by this process, which comes before any of its
+    /// messages and is needed by `set_log_level`
+    pub fn wait_for_context(&self, context_id: &str, timeout: Duration) -> bool {
+        let pid = process::id() as i32;
+        self.wait_for(timeout, |message| match *message {
+            UserMessage::RegisterContext { context_id: ref id, pid: owner, .. } => id == context_id && owner == pid,
+            _ => false
+        }).is_some()
+    }
+
+    /// Panics, listing what was captured, if no matching log message arrives in time.
+    ///
+    /// Used by `assert_logged!`.
+    pub fn assert_logged<F>(&self, app_id: &str, context_id: &str, level: Level, predicate: F, what: &str) -> Record
+        where F: FnMut(&Record) -> bool
+    {
+        match self.wait_for_log(app_id, context_id, level, DEFAULT_TIMEOUT, predicate) {
+            Some(record) => record,
+            None         => {
+                let captured: Vec<String> = self.records().iter().map(|record| {
+                    format!("  {}:{} {:?} \"{}\"", record.app_id, record.context_id, record.level, record.text())
+                }).collect();
+                panic!("No {} message {} logged by {}:{}. Captured log messages:\n{}",
+                       level, what, app_id, context_id, captured.join("\n"));
+            }
+        }
+    }
+
+    /// Sends a log level change for `context_id` to this process, like `dlt-control` would.
+    ///
+    /// The context must have been registered already.
+    pub fn set_log_level(&self, context_id: &str, level: Level) -> ::std::io::Result<()> {
+        let pid = process::id() as i32;
+        let log_level_pos = self.messages().iter().rev().filter_map(|message| match *message {
+            UserMessage::RegisterContext { context_id: ref id, log_level_pos, pid: owner, .. }
+                if id == context_id && owner == pid => Some(log_level_pos),
+            _ => None
+        }).next();
+
+        let log_level_pos = match log_level_pos {
+            Some(log_level_pos) => log_level_pos,
+            None => return Err(::std::io::Error::new(::std::io::ErrorKind::NotFound,
+                                                    format!("context \"{}\" is not registered", context_id)))
+        };
+
+        let mut message = Vec::with_capacity(protocol::USER_HEADER_SIZE + 6);
+        message.extend_from_slice(protocol::USER_HEADER_PATTERN);
+        message.extend_from_slice(&protocol::USER_MESSAGE_LOG_LEVEL.to_ne_bytes());
+        message.push(level as u8);
+        message.push(0); // trace status off
+        message.extend_from_slice(&log_level_pos.to_ne_bytes());
+
+        let app_fifo = self.dir.join("dltpipes").join(format!("dlt{}", pid));
+        OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(app_fifo)?.write_all(&message)
+    }
+}
+
+impl Drop for MockDaemon {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Serializes the tests registering the application, a process has only one. Starts the mock
+/// daemon, which has to come first.
+#[cfg(test)]
+pub(crate) fn exclusive() -> MutexGuard<'static, ()> {
+    static EXCLUSIVE: Mutex<()> = Mutex::new(());
+
+    let guard = match EXCLUSIVE.lock() {
+        Ok(guard)     => guard,
+        Err(poisoned) => poisoned.into_inner()
+    };
+    // A test that failed while the mock was paused doesn't stall the next one
+    MockDaemon::global().resume();
+    guard
+}
+
+/// The `RLOG` application of a test, unregistered before the next test may start
+#[cfg(test)]
+pub(crate) struct TestApplication {
+    _application: Application,
+    _exclusive: MutexGuard<'static, ()>
+}
+
+#[cfg(test)]
+pub(crate) fn register_application() -> TestApplication {
+    let exclusive = exclusive();
+    TestApplication {
+        _application: Application::register("RLOG", "Rust logging").unwrap(),
+        _exclusive: exclusive
+    }
+}
+
+/// Registers a context, once the mock daemon knows it `MockDaemon::set_log_level` works
+#[cfg(test)]
+pub(crate) fn register_and_wait(context_id: &str, description: &str) -> Context {
+    let context = Context::register(context_id, description).unwrap();
+    assert!(MockDaemon::global().wait_for_context(context_id, DEFAULT_TIMEOUT),
+            "The registration of {} never reached the daemon", context_id);
+    context
+}
+
+/// Polls `condition` until it holds or `DEFAULT_TIMEOUT` passed, returns its last value
+#[cfg(test)]
+pub(crate) fn wait_until<F: FnMut() -> bool>(mut condition: F) -> bool {
+    let deadline = Instant::now() + DEFAULT_TIMEOUT;
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    true
+}
+
+/// Points the user library at another FIFO directory, `dlt` and `dltpipes/` are expected in it
+fn set_fifo_base_dir(dir: &Path) {
+    let bytes = dir.as_os_str().as_bytes();
+    unsafe {
+        let base_dir = &mut *ptr::addr_of_mut!(ffi::dltFifoBaseDir);
+        assert!(bytes.len() < base_dir.len(), "FIFO directory path too long: {}", dir.display());
+        for (dst, &src) in base_dir.iter_mut().zip(bytes.iter().chain(Some(&0u8))) {
+            *dst = src as libc::c_char;
+        }
+    }
+}
+
+/// Asserts that the mock daemon received a log message.
+///
+/// ```ignore
+/// assert_logged!("APP", "CTX", dlt::Level::Info);
+/// assert_logged!("APP", "CTX", dlt::Level::Info, contains "part of the text");
+/// assert_logged!("APP", "CTX", dlt::Level::Info, equals "the whole text");
+/// ```
+///
+/// The text of a message is the display of all its arguments, separated by spaces.
+#[macro_export]
+macro_rules! assert_logged {
+    ($app:expr, $ctx:expr, $level:expr) => {
+        $crate::test_util::MockDaemon::global()
+            .assert_logged($app, $ctx, $level, |_| true, "")
+    };
+    ($app:expr, $ctx:expr, $level:expr, contains $text:expr) => {
+        $crate::test_util::MockDaemon::global()
+            .assert_logged($app, $ctx, $level, |record| record.text().contains($text),
+                           &format!("containing {:?}", $text))
+    };
+    ($app:expr, $ctx:expr, $level:expr, equals $text:expr) => {
+        $crate::test_util::MockDaemon::global()
+            .assert_logged($app, $ctx, $level, |record| record.text() == $text,
+                           &format!("equal to {:?}", $text))
+    };
+}
+
+#[test]
+fn hello_from_rust_reaches_the_daemon() {
+    let _app   = register_application();
+    let daemon = MockDaemon::global();
+    let ctx    = register_and_wait("TEST", "Rusty test");
+
+    ctx.log(Level::Info, "Hello from Rust").unwrap();
+    assert_logged!("RLOG", "TEST", Level::Info, equals "Hello from Rust");
+
+    let (changed, notified) = ::std::sync::mpsc::channel();
+    let changed = Mutex::new(changed);
+    ctx.on_level_changed(move |level| { let _ = changed.lock().unwrap().send(level); }).unwrap();
+
+    daemon.set_log_level("TEST", Level::Debug).unwrap();
+    wait_until(|| ctx.is_enabled(Level::Debug));
+    assert_eq!(notified.recv_timeout(DEFAULT_TIMEOUT), Ok(Some(Level::Debug)));
+    assert_eq!(ctx.level(), Some(Level::Debug));
+    ctx.message(Level::Debug).expect("Debug wasn't enabled by the daemon").string("answer").u32(42).send().unwrap();
+    assert_logged!("RLOG", "TEST", Level::Debug, equals "answer 42");
+}
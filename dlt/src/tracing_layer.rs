@@ -0,0 +1,162 @@
+//! `tracing` integration, enabled with the `tracing` feature.
+//!
+//! Each event target is mapped to a context by a `ContextMap`, and registered the first
+//! time the target logs.
+//! Event fields become typed, named, verbose arguments, entering and leaving a span is logged
+//! with the `Verbose` level.
+//!
+//! ```no_run
+//! # extern crate dlt;
+//! # extern crate tracing_subscriber;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! # fn main() {
+//! let _dlt       = dlt::init("RLOG", "Rust logging").unwrap();
+//! let subscriber = tracing_subscriber::registry().with(dlt::DltLayer::new());
+//! # let _ = subscriber;
+//! # }
+//! ```
+
+use std::fmt;
+
+use tracing_core::field::{ Field, Visit };
+use tracing_core::span;
+use tracing_core::{ Event, Subscriber };
+use tracing_subscriber::layer::{ self, Layer };
+use tracing_subscriber::registry::LookupSpan;
+
+use context_map::ContextMap;
+use { Context, Level, Message };
+
+/// Sends `tracing` events and spans to DLT, to be added to a `tracing_subscriber::Registry`
+pub struct DltLayer {
+    contexts: ContextMap,
+    spans: bool
+}
+
+impl DltLayer {
+    /// Layer logging events and span transitions, giving every target a hashed context ID
+    pub fn new() -> DltLayer {
+        DltLayer::with_context_map(ContextMap::new())
+    }
+
+    /// Layer resolving the targets with `contexts`
+    pub fn with_context_map(contexts: ContextMap) -> DltLayer {
+        DltLayer { contexts, spans: true }
+    }
+
+    /// The mapping of targets to contexts
+    pub fn context_map(&self) -> &ContextMap {
+        &self.contexts
+    }
+
+    /// Whether entering and leaving spans is logged, `true` by default
+    pub fn with_spans(mut self, spans: bool) -> DltLayer {
+        self.spans = spans;
+        self
+    }
+
+    /// The context of `target`, registered on first use.
+    ///
+    /// ### Returns
+    /// `None` if the registration failed, the application isn't registered yet for example
+    fn context(&self, target: &str) -> Option<Context> {
+        self.contexts.context(target).ok()
+    }
+
+    fn log_span<S>(&self, id: &span::Id, ctx: &layer::Context<'_, S>, transition: &str)
+        where S: Subscriber + for<'a> LookupSpan<'a>
+    {
+        if !self.spans {
+            return;
+        }
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None       => return
+        };
+        let metadata = span.metadata();
+        if let Some(context) = self.context(metadata.target()) {
+            if let Some(mut message) = context.message(Level::Verbose) {
+                let _ = message.string(transition).string(metadata.name()).send();
+            }
+        }
+    }
+}
+
+impl Default for DltLayer {
+    fn default() -> DltLayer {
+        DltLayer::new()
+    }
+}
+
+impl<S> Layer<S> for DltLayer
+    where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        let metadata = event.metadata();
+        let context  = match self.context(metadata.target()) {
+            Some(context) => context,
+            None          => return
+        };
+
+        if let Some(mut message) = context.message(to_level(metadata.level())) {
+            event.record(&mut FieldWriter { message: &mut message });
+            let _ = message.send();
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        self.log_span(id, &ctx, "enter");
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        self.log_span(id, &ctx, "exit");
+    }
+}
+
+fn to_level(level: &tracing_core::Level) -> Level {
+    match *level {
+        tracing_core::Level::ERROR => Level::Error,
+        tracing_core::Level::WARN  => Level::Warn,
+        tracing_core::Level::INFO  => Level::Info,
+        tracing_core::Level::DEBUG => Level::Debug,
+        _                          => Level::Verbose
+    }
+}
+
+/// Appends the fields of an event as named arguments, the message as an anonymous string
+struct FieldWriter<'m, 'a: 'm> {
+    message: &'m mut Message<'a>
+}
+
+impl<'m, 'a> Visit for FieldWriter<'m, 'a> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.message.named_f64(field.name(), value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.message.named_i64(field.name(), value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.message.named_u64(field.name(), value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.message.named_bool(field.name(), value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.string(value);
+        } else {
+            self.message.named_string(field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{:?}", value);
+        self.record_str(field, &value);
+    }
+}
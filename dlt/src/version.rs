@@ -0,0 +1,70 @@
+//! Version of the user library, checked when the application registers.
+//!
+//! The structures shared with libdlt change between minor versions, so the bindings only fit
+//! the version they were built for. A shared libdlt replaced by another version makes `init`
+//! fail with `Error::VersionMismatch` instead of corrupting memory later on.
+
+use std::ffi::{ CStr, CString };
+use std::fmt;
+
+use libc::{ c_char, size_t };
+
+use ffi;
+use { check, Error, Result };
+
+/// Version of the user library in use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    /// What `dlt_get_version` prints: package version, revision and build date with libdlt
+    pub description: String
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Reads a string printed by the user library
+fn read<F: FnOnce(*mut c_char, size_t)>(print: F) -> String {
+    let mut buffer = [0 as c_char; 256];
+    print(buffer.as_mut_ptr(), buffer.len());
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_string_lossy().trim().to_owned()
+}
+
+/// The version of the user library linked(or of the one `native` follows)
+pub fn version() -> Version {
+    let major = read(|buf, size| unsafe { ffi::dlt_get_major_version(buf, size) });
+    let minor = read(|buf, size| unsafe { ffi::dlt_get_minor_version(buf, size) });
+
+    Version {
+        major: major.parse().unwrap_or(0),
+        minor: minor.parse().unwrap_or(0),
+        description: read(|buf, size| unsafe { ffi::dlt_get_version(buf, size) })
+    }
+}
+
+/// `dlt_user_check_library_version` with the version of the bindings
+pub(crate) fn check_library_version() -> Result<()> {
+    let major = CString::new(ffi::DLT_PACKAGE_MAJOR_VERSION)?;
+    let minor = CString::new(ffi::DLT_PACKAGE_MINOR_VERSION)?;
+
+    match check(unsafe { ffi::dlt_user_check_library_version(major.as_ptr(), minor.as_ptr()) }) {
+        Ok(()) => Ok(()),
+        Err(_) => Err(Error::VersionMismatch {
+            expected: format!("{}.{}", ffi::DLT_PACKAGE_MAJOR_VERSION, ffi::DLT_PACKAGE_MINOR_VERSION),
+            linked: version().to_string()
+        })
+    }
+}
+
+#[test]
+fn library_matches_the_bindings() {
+    assert!(check_library_version().is_ok());
+
+    let version = version();
+    assert_eq!(version.to_string(), format!("{}.{}", ffi::DLT_PACKAGE_MAJOR_VERSION, ffi::DLT_PACKAGE_MINOR_VERSION));
+    assert!(version.description.contains(ffi::DLT_PACKAGE_VERSION));
+}